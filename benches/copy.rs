@@ -0,0 +1,52 @@
+//! Compares [`wait_around::fast_copy`] against `futures::io::copy` draining a full ring.
+//!
+//! Run with `cargo bench --bench copy`.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use futures::{io, prelude::*};
+use wait_around::RingBuffer;
+
+const CAPACITY: usize = 64 * 1024;
+
+fn filled() -> wait_around::Reader {
+    let (tx, rx) = RingBuffer::with_capacity(CAPACITY);
+    let data = vec![0xa5; CAPACITY];
+    tx.write_all(&data).now_or_never().unwrap().unwrap();
+    drop(tx);
+    rx
+}
+
+fn copy(c: &mut Criterion) {
+    let mut group = c.benchmark_group("copy");
+    group.throughput(Throughput::Bytes(CAPACITY as u64));
+
+    group.bench_function("fast_copy", |b| {
+        b.iter_batched(
+            filled,
+            |rx| {
+                let mut sink = io::sink();
+                wait_around::fast_copy(&rx, &mut sink)
+                    .now_or_never()
+                    .unwrap()
+                    .unwrap()
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    group.bench_function("futures_io_copy", |b| {
+        b.iter_batched(
+            filled,
+            |rx| {
+                let mut sink = io::sink();
+                io::copy(rx, &mut sink).now_or_never().unwrap().unwrap()
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, copy);
+criterion_main!(benches);
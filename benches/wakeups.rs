@@ -0,0 +1,54 @@
+//! Tight read/write loops that park and wake through a real, reference-counted waker, so the
+//! `Arc` traffic that re-registering the same waker would cost shows up in the timings.
+//!
+//! Run with `cargo bench --bench wakeups`.
+
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Context;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use futures::io::{AsyncRead, AsyncWrite};
+use futures::task::{waker, ArcWake};
+use wait_around::RingBuffer;
+
+struct Task;
+
+impl ArcWake for Task {
+    fn wake_by_ref(_: &Arc<Self>) {}
+}
+
+fn register(c: &mut Criterion) {
+    let waker = waker(Arc::new(Task));
+    let mut cx = Context::from_waker(&waker);
+    let mut group = c.benchmark_group("register");
+
+    // Reader parks on an empty ring, the writer hands it a chunk (waking it), and the reader
+    // drains it before parking again.
+    group.bench_function("ping_pong", |b| {
+        let (mut tx, mut rx) = RingBuffer::with_capacity(64);
+        let mut buf = [0; 16];
+        b.iter(|| {
+            assert!(Pin::new(&mut rx).poll_read(&mut cx, &mut buf).is_pending());
+            let _ = Pin::new(&mut tx).poll_write(&mut cx, &[1; 16]);
+            let _ = Pin::new(&mut rx).poll_read(&mut cx, &mut buf);
+        })
+    });
+
+    // The same task polls an empty ring over and over, as a select loop would after being woken
+    // by something else; only the first poll needs to store the waker.
+    group.bench_function("repoll", |b| {
+        let (_tx, mut rx) = RingBuffer::with_capacity(64);
+        let mut buf = [0; 16];
+        b.iter(|| {
+            for _ in 0..16 {
+                assert!(Pin::new(&mut rx).poll_read(&mut cx, &mut buf).is_pending());
+            }
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, register);
+criterion_main!(benches);
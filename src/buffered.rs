@@ -0,0 +1,300 @@
+//! Writers that stage bytes locally and push them into the ring in larger chunks, saving a copy
+//! and a wakeup per small write.
+
+use crate::Writer;
+use alloc::vec::Vec;
+use core::pin::Pin;
+use futures::{
+    io::{AsyncWrite, Error},
+    ready,
+    task::{Context, Poll},
+};
+
+/// Like `std::io::BufWriter`: small writes are staged and only pushed into the ring when the
+/// staging buffer would overflow, or on flush/close.
+pub struct BufWriter {
+    inner: Writer,
+    buf: Vec<u8>,
+    capacity: usize,
+    written: usize,
+}
+
+impl BufWriter {
+    pub fn with_capacity(capacity: usize, inner: Writer) -> BufWriter {
+        BufWriter {
+            inner,
+            buf: Vec::with_capacity(capacity),
+            capacity,
+            written: 0,
+        }
+    }
+
+    /// Bytes staged but not yet pushed into the ring.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf[self.written..]
+    }
+
+    /// Returns the underlying writer. Anything still staged is discarded, so flush first.
+    pub fn into_inner(self) -> Writer {
+        self.inner
+    }
+
+    /// Pushes staged bytes into the ring, parking if it fills up.
+    fn poll_flush_buf(&mut self, cx: &mut Context) -> Poll<Result<(), Error>> {
+        while self.written < self.buf.len() {
+            let n = ready!(self.inner.poll_write_ref(cx, &self.buf[self.written..]))?;
+            self.written += n;
+        }
+        self.buf.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for BufWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        let this = self.get_mut();
+        if this.buf.len() + buf.len() > this.capacity {
+            ready!(this.poll_flush_buf(cx))?;
+        }
+
+        if buf.len() >= this.capacity {
+            // Staging wouldn't save anything; go straight to the ring.
+            this.inner.poll_write_ref(cx, buf).map_err(Error::from)
+        } else {
+            this.buf.extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_flush_buf(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_flush_buf(cx))?;
+        Pin::new(&mut this.inner).poll_close(cx)
+    }
+}
+
+/// Like `std::io::LineWriter`: bytes are staged until a newline is written, at which point the
+/// completed line(s) are pushed into the ring. Partial lines wait for a newline, a flush or close,
+/// or for the staging buffer to fill.
+pub struct LineWriter {
+    inner: BufWriter,
+}
+
+impl LineWriter {
+    pub fn with_capacity(capacity: usize, inner: Writer) -> LineWriter {
+        LineWriter {
+            inner: BufWriter::with_capacity(capacity, inner),
+        }
+    }
+
+    /// Bytes staged but not yet pushed into the ring.
+    pub fn buffer(&self) -> &[u8] {
+        self.inner.buffer()
+    }
+
+    /// Returns the underlying writer. Anything still staged is discarded, so flush first.
+    pub fn into_inner(self) -> Writer {
+        self.inner.into_inner()
+    }
+}
+
+impl AsyncWrite for LineWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        let this = self.get_mut();
+
+        // A completed line from an earlier write couldn't be pushed yet; finish that first.
+        if this.inner.buffer().last() == Some(&b'\n') {
+            ready!(this.inner.poll_flush_buf(cx))?;
+        }
+
+        let line_end = match buf.iter().rposition(|&b| b == b'\n') {
+            Some(idx) => idx + 1,
+            None => return Pin::new(&mut this.inner).poll_write(cx, buf),
+        };
+
+        // Accept everything through the last newline and try to push it out; if the ring is full
+        // it stays staged and goes out on the next write or flush. The partial line after it is
+        // left for the caller's next write.
+        let n = ready!(Pin::new(&mut this.inner).poll_write(cx, &buf[..line_end]))?;
+        let _ = this.inner.poll_flush_buf(cx)?;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.get_mut().inner).poll_close(cx)
+    }
+}
+
+/// Stages writes until `threshold` bytes have accumulated, then pushes them into the ring right
+/// away, without waiting for a flush. Suits binary protocols with a natural record size.
+pub struct AutoFlushWriter {
+    inner: BufWriter,
+    threshold: usize,
+}
+
+impl AutoFlushWriter {
+    /// A `threshold` of 0 is treated as 1, i.e. every byte is pushed as it's written.
+    pub fn new(threshold: usize, inner: Writer) -> AutoFlushWriter {
+        let threshold = threshold.max(1);
+        AutoFlushWriter {
+            inner: BufWriter::with_capacity(threshold, inner),
+            threshold,
+        }
+    }
+
+    /// Bytes staged but not yet pushed into the ring.
+    pub fn buffer(&self) -> &[u8] {
+        self.inner.buffer()
+    }
+
+    /// Returns the underlying writer. Anything still staged is discarded, so flush first.
+    pub fn into_inner(self) -> Writer {
+        self.inner.into_inner()
+    }
+}
+
+impl AsyncWrite for AutoFlushWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        let this = self.get_mut();
+
+        // The last batch hit the threshold but the ring was full; that's our backpressure.
+        if this.inner.buffer().len() >= this.threshold {
+            ready!(this.inner.poll_flush_buf(cx))?;
+        }
+
+        let n = buf.len().min(this.threshold - this.inner.buffer().len());
+        this.inner.buf.extend_from_slice(&buf[..n]);
+        if this.inner.buffer().len() == this.threshold {
+            let _ = this.inner.poll_flush_buf(cx)?;
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.get_mut().inner).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RingBuffer;
+    use futures::prelude::*;
+
+    #[test]
+    fn small_writes_are_staged_until_flush() {
+        let (tx, mut rx) = RingBuffer::with_capacity(4);
+        let mut tx = BufWriter::with_capacity(3, tx);
+
+        tx.write_all(&[1, 2]).now_or_never().unwrap().unwrap();
+        assert!(rx.is_empty());
+
+        // Overflowing the staging buffer pushes what was staged first.
+        tx.write_all(&[3, 4]).now_or_never().unwrap().unwrap();
+        assert_eq!(rx.len(), 2);
+        assert_eq!(tx.buffer(), [3, 4]);
+
+        // Only two of the staged bytes fit, so flushing parks until the reader catches up.
+        tx.write_all(&[5]).now_or_never().unwrap().unwrap();
+        assert!(tx.flush().now_or_never().is_none());
+        let mut buf = [0; 4];
+        assert_eq!(rx.read(&mut buf).now_or_never().unwrap().unwrap(), 4);
+        assert_eq!(buf, [1, 2, 3, 4]);
+
+        tx.close().now_or_never().unwrap().unwrap();
+        let mut rest = Vec::new();
+        rx.read_to_end(&mut rest).now_or_never().unwrap().unwrap();
+        assert_eq!(rest, [5]);
+    }
+
+    #[test]
+    fn close_waits_for_staged_bytes() {
+        let (tx, mut rx) = RingBuffer::with_capacity(2);
+        let mut tx = BufWriter::with_capacity(4, tx);
+        tx.write_all(&[1, 2, 3]).now_or_never().unwrap().unwrap();
+
+        // The ring can't take everything yet, so close must not complete (or signal EOF).
+        assert!(tx.close().now_or_never().is_none());
+        let mut buf = [0; 2];
+        assert_eq!(rx.read(&mut buf).now_or_never().unwrap().unwrap(), 2);
+
+        tx.close().now_or_never().unwrap().unwrap();
+        let mut rest = Vec::new();
+        rx.read_to_end(&mut rest).now_or_never().unwrap().unwrap();
+        assert_eq!(rest, [3]);
+    }
+
+    #[test]
+    fn lines_are_pushed_at_newlines() {
+        let (tx, mut rx) = RingBuffer::with_capacity(16);
+        let mut tx = LineWriter::with_capacity(8, tx);
+
+        tx.write_all(b"ab").now_or_never().unwrap().unwrap();
+        assert!(rx.is_empty());
+
+        tx.write_all(b"c\nde").now_or_never().unwrap().unwrap();
+        assert_eq!(rx.len(), 4);
+        assert_eq!(tx.buffer(), b"de");
+
+        tx.flush().now_or_never().unwrap().unwrap();
+        assert_eq!(rx.len(), 6);
+
+        let mut buf = [0; 6];
+        rx.read_exact(&mut buf).now_or_never().unwrap().unwrap();
+        assert_eq!(&buf, b"abc\nde");
+    }
+
+    #[test]
+    fn auto_flush_at_threshold() {
+        let (tx, mut rx) = RingBuffer::with_capacity(16);
+        let mut tx = AutoFlushWriter::new(4, tx);
+
+        tx.write_all(&[1, 2, 3]).now_or_never().unwrap().unwrap();
+        assert!(rx.is_empty());
+
+        // Reaching the threshold exactly pushes the batch.
+        tx.write_all(&[4]).now_or_never().unwrap().unwrap();
+        assert_eq!(rx.len(), 4);
+        assert!(tx.buffer().is_empty());
+
+        // Crossing it pushes a full batch and stages the remainder.
+        tx.write_all(&[5, 6, 7, 8, 9])
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert_eq!(rx.len(), 8);
+        assert_eq!(tx.buffer(), [9]);
+
+        tx.close().now_or_never().unwrap().unwrap();
+        let mut out = Vec::new();
+        rx.read_to_end(&mut out).now_or_never().unwrap().unwrap();
+        assert_eq!(out, [1, 2, 3, 4, 5, 6, 7, 8, 9]);
+    }
+}
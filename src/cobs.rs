@@ -0,0 +1,262 @@
+//! Zero-delimited message framing using [Consistent Overhead Byte
+//! Stuffing](https://en.wikipedia.org/wiki/Consistent_Overhead_Byte_Stuffing).
+
+use crate::{Reader, Writer};
+use alloc::vec::Vec;
+use futures::{
+    future,
+    io::{Error, ErrorKind},
+    task::{Context, Poll},
+};
+
+pub struct CobsWriter {
+    inner: Writer,
+    scratch: Vec<u8>,
+}
+
+impl CobsWriter {
+    pub fn new(inner: Writer) -> CobsWriter {
+        CobsWriter {
+            inner,
+            scratch: Vec::new(),
+        }
+    }
+
+    /// Encodes `msg` and writes it to the ring, followed by the zero delimiter.
+    ///
+    /// The encoded frame (message plus roughly one byte in 254 of overhead) must fit in the ring,
+    /// since the reader only consumes complete frames.
+    pub async fn send(&mut self, msg: &[u8]) -> Result<(), Error> {
+        self.scratch.clear();
+        encode(msg, &mut self.scratch);
+        Ok(self.inner.write_all(&self.scratch).await?)
+    }
+
+    pub fn into_inner(self) -> Writer {
+        self.inner
+    }
+}
+
+pub struct CobsReader {
+    inner: Reader,
+    resync: bool,
+}
+
+impl CobsReader {
+    pub fn new(inner: Reader) -> CobsReader {
+        CobsReader {
+            inner,
+            resync: false,
+        }
+    }
+
+    /// Waits for a complete frame to be buffered, then decodes and consumes it.
+    ///
+    /// Returns `None` once the writer has closed and every frame has been read. If the ring fills
+    /// up without a delimiter the frame can never complete; the buffered bytes are dropped, the
+    /// rest of that frame is skipped, and an `InvalidData` error is returned. If the writer closes
+    /// mid-frame, the partial frame is dropped and `UnexpectedEof` is returned; the next call
+    /// returns `None`.
+    pub fn poll_recv(&mut self, cx: &mut Context) -> Poll<Option<Result<Vec<u8>, Error>>> {
+        let mut rb = self.inner.rb.borrow_mut();
+
+        if self.resync {
+            let (first, second) = rb.as_slices();
+            match first.iter().chain(second).position(|&b| b == 0) {
+                Some(end) => {
+                    rb.read(end + 1);
+                    rb.wake_write();
+                    self.resync = false;
+                }
+                None if rb.writer_closed && rb.len() == 0 => {
+                    self.resync = false;
+                    return Poll::Ready(None);
+                }
+                None => {
+                    let len = rb.len();
+                    rb.read(len);
+                    rb.wake_write();
+                    if rb.writer_closed {
+                        self.resync = false;
+                        return Poll::Ready(Some(Err(ErrorKind::UnexpectedEof.into())));
+                    }
+                    rb.park_read(cx.waker());
+                    return Poll::Pending;
+                }
+            }
+        }
+
+        let (first, second) = rb.as_slices();
+        let end = match first.iter().chain(second).position(|&b| b == 0) {
+            Some(end) => end,
+            None if rb.len() == rb.data.len() => {
+                let len = rb.len();
+                rb.read(len);
+                rb.wake_write();
+                self.resync = true;
+                return Poll::Ready(Some(Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "COBS frame larger than ring capacity",
+                ))));
+            }
+            None if rb.writer_closed && rb.len() == 0 => return Poll::Ready(None),
+            None if rb.writer_closed => {
+                let len = rb.len();
+                rb.read(len);
+                return Poll::Ready(Some(Err(ErrorKind::UnexpectedEof.into())));
+            }
+            None => {
+                rb.park_read(cx.waker());
+                return Poll::Pending;
+            }
+        };
+
+        let mut msg = Vec::with_capacity(end);
+        let result = decode(first.iter().chain(second).take(end).copied(), &mut msg);
+        rb.read(end + 1);
+        rb.wake_write();
+        Poll::Ready(Some(result.map(|()| msg)))
+    }
+
+    pub async fn recv(&mut self) -> Option<Result<Vec<u8>, Error>> {
+        future::poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    pub fn into_inner(self) -> Reader {
+        self.inner
+    }
+}
+
+fn encode(msg: &[u8], out: &mut Vec<u8>) {
+    let mut code_idx = out.len();
+    let mut code = 1u8;
+    out.push(0);
+
+    for &b in msg {
+        if b != 0 {
+            out.push(b);
+            code += 1;
+        }
+
+        if b == 0 || code == 0xFF {
+            out[code_idx] = code;
+            code_idx = out.len();
+            code = 1;
+            out.push(0);
+        }
+    }
+
+    out[code_idx] = code;
+    out.push(0);
+}
+
+fn decode(frame: impl Iterator<Item = u8>, out: &mut Vec<u8>) -> Result<(), Error> {
+    let invalid = || Error::new(ErrorKind::InvalidData, "malformed COBS frame");
+
+    let mut frame = frame.peekable();
+    while let Some(code) = frame.next() {
+        if code == 0 {
+            return Err(invalid());
+        }
+
+        for _ in 1..code {
+            out.push(frame.next().ok_or_else(invalid)?);
+        }
+
+        if code != 0xFF && frame.peek().is_some() {
+            out.push(0);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RingBuffer;
+    use futures::prelude::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn frame_spans_wrap() {
+        let (tx, mut rx) = RingBuffer::with_capacity(8);
+        let mut tx = CobsWriter::new(tx);
+
+        // Advance the indices so the next frame straddles the end of storage.
+        tx.inner.write_all(&[1; 6]).now_or_never().unwrap().unwrap();
+        rx.read_exact(&mut [0; 6]).now_or_never().unwrap().unwrap();
+
+        let mut rx = CobsReader::new(rx);
+        tx.send(&[0, 7, 0, 9]).now_or_never().unwrap().unwrap();
+        let msg = rx.recv().now_or_never().unwrap().unwrap().unwrap();
+        assert_eq!(msg, [0, 7, 0, 9]);
+    }
+
+    #[test]
+    fn oversized_frame_is_rejected() {
+        let (tx, rx) = RingBuffer::with_capacity(4);
+        let mut rx = CobsReader::new(rx);
+
+        tx.write_all(&[1, 2, 3, 4]).now_or_never().unwrap().unwrap();
+        let err = rx.recv().now_or_never().unwrap().unwrap().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        // The tail of the bad frame is skipped and the next one decodes cleanly.
+        tx.write_all(&[5, 0, 2, 6]).now_or_never().unwrap().unwrap();
+        assert!(rx.recv().now_or_never().is_none());
+        tx.write_all(&[0]).now_or_never().unwrap().unwrap();
+        assert_eq!(rx.recv().now_or_never().unwrap().unwrap().unwrap(), [6]);
+
+        // Closing while skipping ends the stream rather than erroring forever.
+        tx.write_all(&[1, 2, 3, 4]).now_or_never().unwrap().unwrap();
+        let err = rx.recv().now_or_never().unwrap().unwrap().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        drop(tx);
+        assert!(rx.recv().now_or_never().unwrap().is_none());
+        assert!(rx.recv().now_or_never().unwrap().is_none());
+
+        let (tx, rx) = RingBuffer::with_capacity(4);
+        let mut rx = CobsReader::new(rx);
+        tx.write_all(&[1, 2, 3, 4]).now_or_never().unwrap().unwrap();
+        let err = rx.recv().now_or_never().unwrap().unwrap().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        tx.write_all(&[5]).now_or_never().unwrap().unwrap();
+        drop(tx);
+        let err = rx.recv().now_or_never().unwrap().unwrap().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        assert!(rx.recv().now_or_never().unwrap().is_none());
+    }
+
+    #[test]
+    fn clean_close_ends_stream() {
+        let (tx, rx) = RingBuffer::with_capacity(8);
+        let (mut tx, mut rx) = (CobsWriter::new(tx), CobsReader::new(rx));
+
+        tx.send(&[1, 2]).now_or_never().unwrap().unwrap();
+        drop(tx);
+        assert_eq!(rx.recv().now_or_never().unwrap().unwrap().unwrap(), [1, 2]);
+        assert!(rx.recv().now_or_never().unwrap().is_none());
+
+        // A frame cut off by the close is still an error.
+        let (tx, rx) = RingBuffer::with_capacity(8);
+        let mut rx = CobsReader::new(rx);
+        tx.write_all(&[3, 1]).now_or_never().unwrap().unwrap();
+        drop(tx);
+        let err = rx.recv().now_or_never().unwrap().unwrap().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    proptest! {
+        #[test]
+        fn round_trip(msg in any::<Vec<u8>>()) {
+            let mut encoded = Vec::new();
+            encode(&msg, &mut encoded);
+            prop_assert_eq!(encoded.iter().position(|&b| b == 0), Some(encoded.len() - 1));
+
+            let mut decoded = Vec::new();
+            decode(encoded[..encoded.len() - 1].iter().copied(), &mut decoded).unwrap();
+            prop_assert_eq!(decoded, msg);
+        }
+    }
+}
@@ -0,0 +1,258 @@
+//! Length-delimited framing: each frame is a 4-byte big-endian payload length followed by the
+//! payload itself.
+
+use crate::{Reader, Writer};
+use alloc::vec::Vec;
+use core::pin::Pin;
+use futures::{
+    io::{AsyncWrite, Error, ErrorKind},
+    ready,
+    sink::Sink,
+    stream::{self, Stream},
+    task::{Context, Poll},
+};
+
+const HEADER_LEN: usize = 4;
+
+pub struct FrameReader {
+    inner: Reader,
+    failed: bool,
+}
+
+impl FrameReader {
+    pub fn new(inner: Reader) -> FrameReader {
+        FrameReader {
+            inner,
+            failed: false,
+        }
+    }
+
+    /// Waits for a complete frame to be buffered, then consumes it and returns the payload.
+    ///
+    /// Returns `None` once the writer has closed and every frame has been read. A frame whose
+    /// header and payload can't fit in the ring is an `InvalidData` error, and a frame cut off by
+    /// the writer closing is `UnexpectedEof`.
+    ///
+    /// Neither error leaves a way to find where a next frame would start, so after either one
+    /// every later call returns `None`.
+    pub fn poll_frame(&mut self, cx: &mut Context) -> Poll<Option<Result<Vec<u8>, Error>>> {
+        if self.failed {
+            return Poll::Ready(None);
+        }
+
+        let mut rb = self.inner.rb.borrow_mut();
+        let len = rb.len();
+        let (first, second) = rb.as_slices();
+
+        let mut header = [0; HEADER_LEN];
+        let frame_len = if len >= HEADER_LEN {
+            for (dst, &src) in header.iter_mut().zip(first.iter().chain(second)) {
+                *dst = src;
+            }
+            let payload_len = u32::from_be_bytes(header) as usize;
+            if payload_len > rb.data.len().saturating_sub(HEADER_LEN) {
+                self.failed = true;
+                return Poll::Ready(Some(Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "frame larger than ring capacity",
+                ))));
+            }
+            Some(HEADER_LEN + payload_len)
+        } else {
+            None
+        };
+
+        match frame_len {
+            Some(frame_len) if len >= frame_len => {
+                let payload: Vec<u8> = first
+                    .iter()
+                    .chain(second)
+                    .take(frame_len)
+                    .skip(HEADER_LEN)
+                    .copied()
+                    .collect();
+                rb.read(frame_len);
+                rb.wake_write();
+                Poll::Ready(Some(Ok(payload)))
+            }
+            _ if rb.writer_closed && len == 0 => Poll::Ready(None),
+            _ if rb.writer_closed => {
+                self.failed = true;
+                Poll::Ready(Some(Err(ErrorKind::UnexpectedEof.into())))
+            }
+            _ => {
+                rb.park_read(cx.waker());
+                Poll::Pending
+            }
+        }
+    }
+
+    /// Yields one payload per complete frame, ending at EOF.
+    pub fn into_stream(mut self) -> impl Stream<Item = Result<Vec<u8>, Error>> {
+        stream::poll_fn(move |cx| self.poll_frame(cx))
+    }
+
+    pub fn into_inner(self) -> Reader {
+        self.inner
+    }
+}
+
+/// Writes each item sent to it as one frame.
+///
+/// Only one frame is staged at a time: `poll_ready` resolves once the previous frame has been
+/// pushed into the ring, so a full ring applies backpressure to the sender.
+pub struct FrameWriter {
+    inner: Writer,
+    pending: Vec<u8>,
+    written: usize,
+}
+
+impl FrameWriter {
+    pub fn new(inner: Writer) -> FrameWriter {
+        FrameWriter {
+            inner,
+            pending: Vec::new(),
+            written: 0,
+        }
+    }
+
+    pub fn into_inner(self) -> Writer {
+        self.inner
+    }
+
+    fn poll_pending(&mut self, cx: &mut Context) -> Poll<Result<(), Error>> {
+        while self.written < self.pending.len() {
+            let n = ready!(self.inner.poll_write_ref(cx, &self.pending[self.written..]))?;
+            self.written += n;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Sink<&[u8]> for FrameWriter {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Error>> {
+        self.get_mut().poll_pending(cx)
+    }
+
+    /// Stages `payload` as the next frame. Fails with `InvalidInput` if the header and payload
+    /// together can't fit in the ring, since the reader could never see the whole frame.
+    fn start_send(self: Pin<&mut Self>, payload: &[u8]) -> Result<(), Error> {
+        let this = self.get_mut();
+        let capacity = this.inner.rb.borrow().data.len();
+        if HEADER_LEN + payload.len() > capacity || payload.len() > u32::MAX as usize {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "frame larger than ring capacity",
+            ));
+        }
+
+        this.pending.clear();
+        this.pending
+            .extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        this.pending.extend_from_slice(payload);
+        this.written = 0;
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Error>> {
+        self.get_mut().poll_pending(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        Pin::new(&mut this.inner).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RingBuffer;
+    use futures::prelude::*;
+
+    #[test]
+    fn stream_yields_whole_frames() {
+        let (tx, rx) = RingBuffer::with_capacity(8);
+        let mut frames = FrameReader::new(rx).into_stream();
+
+        tx.write_all(&[0, 0, 0, 3, 1, 2])
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert!(frames.next().now_or_never().is_none());
+
+        tx.write_all(&[3]).now_or_never().unwrap().unwrap();
+        let frame = frames.next().now_or_never().unwrap().unwrap().unwrap();
+        assert_eq!(frame, [1, 2, 3]);
+
+        // This header wraps around the end of storage.
+        tx.write_all(&[0, 0, 0, 0]).now_or_never().unwrap().unwrap();
+        drop(tx);
+        let frame = frames.next().now_or_never().unwrap().unwrap().unwrap();
+        assert!(frame.is_empty());
+        assert!(frames.next().now_or_never().unwrap().is_none());
+    }
+
+    #[test]
+    fn oversized_header_ends_stream() {
+        let (tx, rx) = RingBuffer::with_capacity(8);
+        let mut frames = FrameReader::new(rx).into_stream();
+
+        tx.write_all(&[0, 0, 0, 5, 1, 2, 3, 4])
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let err = frames.next().now_or_never().unwrap().unwrap().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(frames.next().now_or_never().unwrap().is_none());
+        assert!(frames.next().now_or_never().unwrap().is_none());
+    }
+
+    #[test]
+    fn truncated_frame_ends_stream() {
+        for partial in [&[0, 0][..], &[0, 0, 0, 3, 1][..]].iter() {
+            let (tx, rx) = RingBuffer::with_capacity(8);
+            let mut frames = FrameReader::new(rx).into_stream();
+
+            tx.write_all(partial).now_or_never().unwrap().unwrap();
+            drop(tx);
+            let err = frames.next().now_or_never().unwrap().unwrap().unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+            assert!(frames.next().now_or_never().unwrap().is_none());
+            assert!(frames.next().now_or_never().unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn sink_round_trips_through_stream() {
+        let (tx, rx) = RingBuffer::with_capacity(8);
+        let mut sink = FrameWriter::new(tx);
+        let mut frames = FrameReader::new(rx).into_stream();
+
+        sink.send(&[1, 2, 3][..]).now_or_never().unwrap().unwrap();
+
+        // No room for a second frame until the first is read.
+        assert!(sink.send(&[4, 5][..]).now_or_never().is_none());
+        assert_eq!(
+            frames.next().now_or_never().unwrap().unwrap().unwrap(),
+            [1, 2, 3]
+        );
+        sink.flush().now_or_never().unwrap().unwrap();
+        sink.close().now_or_never().unwrap().unwrap();
+        assert_eq!(
+            frames.next().now_or_never().unwrap().unwrap().unwrap(),
+            [4, 5]
+        );
+        assert!(frames.next().now_or_never().unwrap().is_none());
+
+        let err = FrameWriter::new(RingBuffer::with_capacity(8).0)
+            .send(&[0; 5][..])
+            .now_or_never()
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+}
@@ -1,13 +1,38 @@
-#[cfg(feature = "no_std")]
+#![cfg_attr(all(feature = "no_std", not(test)), no_std)]
+
 extern crate alloc;
-#[cfg(not(feature = "no_std"))]
-extern crate std as alloc;
 
-use alloc::{rc::Rc, vec::Vec};
-use core::{cell::RefCell, pin::Pin};
+pub mod buffered;
+#[cfg(feature = "checksum")]
+mod checksum;
+pub mod cobs;
+pub mod frame;
+#[cfg(any(feature = "latency", feature = "rate"))]
+pub mod stats;
+
+#[cfg(feature = "checksum")]
+use checksum::Crc32;
+#[cfg(any(feature = "latency", feature = "rate"))]
+use stats::Clock;
+#[cfg(feature = "rate")]
+use stats::RateSampler;
+#[cfg(feature = "latency")]
+use stats::{LatencyTracker, Stats};
+
+use alloc::{boxed::Box, rc::Rc, sync::Arc, vec::Vec};
+use core::{
+    cell::{Cell, Ref, RefCell, RefMut},
+    fmt,
+    mem::MaybeUninit,
+    num::NonZeroUsize,
+    ops::{Deref, Range},
+    pin::Pin,
+};
 use futures::{
-    io::Error,
+    future,
+    io::{Error, ErrorKind, IoSlice, IoSliceMut},
     prelude::*,
+    ready,
     task::{Context, Poll, Waker},
 };
 
@@ -15,18 +40,180 @@ pub struct RingBuffer {
     data: Vec<u8>,
     read_idx: usize,
     write_idx: usize,
-    waker: Option<Waker>,
+    read_waker: Option<Waker>,
+    write_waker: Option<Waker>,
+    /// Kept apart from the read/write wakers so a task watching for the peer to go away doesn't
+    /// displace one that is reading or writing.
+    writer_closed_waker: Option<Waker>,
+    reader_gone_waker: Option<Waker>,
+    reader_gone: bool,
+    writer_closed: bool,
+    busy_poll: bool,
+    wrap_splits: u64,
+    on_full: Option<Box<dyn FnMut()>>,
+    on_empty: Option<Box<dyn FnMut()>>,
+    pool: Option<Box<dyn BufferPool>>,
+    #[cfg(feature = "checksum")]
+    write_crc: Crc32,
+    #[cfg(feature = "checksum")]
+    read_crc: Crc32,
+    #[cfg(feature = "urgent")]
+    urgent: Option<u8>,
+    #[cfg(any(feature = "latency", feature = "rate"))]
+    clock: Option<Box<dyn Clock>>,
+    #[cfg(feature = "latency")]
+    latency: LatencyTracker,
+    #[cfg(feature = "rate")]
+    write_rate: RateSampler,
+    #[cfg(feature = "rate")]
+    read_rate: RateSampler,
 }
 
 impl RingBuffer {
     pub fn with_capacity(n: usize) -> (Writer, Reader) {
-        let rb = Rc::new(RefCell::new(RingBuffer {
-            data: vec![0; n],
+        RingBuffer::new(zeroed(n)).split()
+    }
+
+    /// Like [`RingBuffer::with_capacity`], but reports a zero capacity (which could never pass a
+    /// byte) or a failed allocation instead of deadlocking or aborting.
+    pub fn try_with_capacity(n: usize) -> Result<(Writer, Reader), AllocError> {
+        if n == 0 {
+            return Err(AllocError::ZeroCapacity);
+        }
+        let mut data = Vec::new();
+        data.try_reserve_exact(n)
+            .map_err(|_| AllocError::OutOfMemory)?;
+        data.resize(n, 0);
+        Ok(RingBuffer::new(data).split())
+    }
+
+    /// Like [`RingBuffer::with_capacity`], but for busy-poll environments that re-poll on their
+    /// own schedule: no wakers are ever stored, so a half that returns `Pending` will not be woken
+    /// and must be polled again by the caller.
+    pub fn with_capacity_busy_poll(n: usize) -> (Writer, Reader) {
+        let mut rb = RingBuffer::new(zeroed(n));
+        rb.busy_poll = true;
+        rb.split()
+    }
+
+    /// Two rings of capacity `n` each, one per direction, bundled so each end reads what the
+    /// other writes. Handy as an in-process stand-in for a socket.
+    pub fn duplex(n: usize) -> (Duplex, Duplex) {
+        let (a_tx, b_rx) = RingBuffer::with_capacity(n);
+        let (b_tx, a_rx) = RingBuffer::with_capacity(n);
+        let a = Duplex {
+            writer: a_tx,
+            reader: a_rx,
+        };
+        let b = Duplex {
+            writer: b_tx,
+            reader: b_rx,
+        };
+        (a, b)
+    }
+
+    /// Like [`RingBuffer::with_capacity`], but takes storage from `pool` and hands it back once
+    /// both halves are gone, so rings that come and go don't each pay for an allocation.
+    pub fn with_capacity_pooled(n: usize, pool: impl BufferPool + 'static) -> (Writer, Reader) {
+        let mut data = pool.acquire(n);
+        data.clear();
+        data.resize(n, 0);
+        let mut rb = RingBuffer::new(data);
+        rb.pool = Some(Box::new(pool));
+        rb.split()
+    }
+
+    /// Like [`RingBuffer::with_capacity`], but keeps time with `clock` so the ring can report
+    /// write-to-read latency (`latency` feature) and throughput (`rate` feature).
+    #[cfg(any(feature = "latency", feature = "rate"))]
+    pub fn with_clock(n: usize, clock: impl Clock + 'static) -> (Writer, Reader) {
+        let mut rb = RingBuffer::new(zeroed(n));
+        rb.clock = Some(Box::new(clock));
+        rb.split()
+    }
+
+    /// Rebuilds a ring from a [`RingSnapshot`], rejecting snapshots whose indices don't describe
+    /// a valid state with `InvalidData`.
+    pub fn restore(snapshot: RingSnapshot) -> Result<(Writer, Reader), Error> {
+        let RingSnapshot {
+            data,
+            read_idx,
+            write_idx,
+            capacity,
+        } = snapshot;
+
+        let invalid = |msg| Err(Error::new(ErrorKind::InvalidData, msg));
+        if data.len() != capacity {
+            return invalid("snapshot data doesn't match its capacity");
+        }
+        // Indices run below twice the capacity; an empty ring only has index 0.
+        let in_range = |idx| idx == 0 || idx < 2 * capacity;
+        if !in_range(read_idx) || !in_range(write_idx) {
+            return invalid("snapshot index out of range");
+        }
+
+        let mut rb = RingBuffer::new(data);
+        rb.read_idx = read_idx;
+        rb.write_idx = write_idx;
+        if rb.len() > capacity {
+            return invalid("snapshot holds more data than its capacity");
+        }
+        Ok(rb.split())
+    }
+
+    fn snapshot(&self) -> RingSnapshot {
+        RingSnapshot {
+            data: self.data.clone(),
+            read_idx: self.read_idx,
+            write_idx: self.write_idx,
+            capacity: self.data.len(),
+        }
+    }
+
+    fn new(data: Vec<u8>) -> RingBuffer {
+        RingBuffer {
+            data,
             read_idx: 0,
             write_idx: 0,
-            waker: None,
-        }));
-        (Writer { rb: rb.clone() }, Reader { rb })
+            read_waker: None,
+            write_waker: None,
+            writer_closed_waker: None,
+            reader_gone_waker: None,
+            reader_gone: false,
+            writer_closed: false,
+            busy_poll: false,
+            wrap_splits: 0,
+            on_full: None,
+            on_empty: None,
+            pool: None,
+            #[cfg(feature = "checksum")]
+            write_crc: Crc32::new(),
+            #[cfg(feature = "checksum")]
+            read_crc: Crc32::new(),
+            #[cfg(feature = "urgent")]
+            urgent: None,
+            #[cfg(any(feature = "latency", feature = "rate"))]
+            clock: None,
+            #[cfg(feature = "latency")]
+            latency: LatencyTracker::default(),
+            #[cfg(feature = "rate")]
+            write_rate: RateSampler::default(),
+            #[cfg(feature = "rate")]
+            read_rate: RateSampler::default(),
+        }
+    }
+
+    fn split(self) -> (Writer, Reader) {
+        let rb = Rc::new(Shared::new(self));
+        let writer = Writer {
+            rb: rb.clone(),
+            max_transfer: usize::MAX,
+        };
+        let reader = Reader {
+            rb,
+            max_transfer: usize::MAX,
+        };
+        (writer, reader)
     }
 
     fn wrap(&self, mut idx: usize) -> usize {
@@ -38,21 +225,93 @@ impl RingBuffer {
     }
 
     fn read(&mut self, amount: usize) {
+        #[cfg(feature = "checksum")]
+        {
+            let (first, second) = self.ranges(self.read_idx, amount);
+            self.read_crc.update(&self.data[first]);
+            self.read_crc.update(&self.data[second]);
+        }
+
+        #[cfg(any(feature = "latency", feature = "rate"))]
+        if let Some(clock) = &self.clock {
+            let now = clock.now();
+            #[cfg(feature = "latency")]
+            self.latency.read(now, amount);
+            #[cfg(feature = "rate")]
+            self.read_rate.record(now, clock.ticks_per_second(), amount);
+        }
+
+        let was_empty = self.len() == 0;
         self.read_idx += amount;
 
         let capacity = self.data.len();
         if self.read_idx >= 2 * capacity {
             self.read_idx -= 2 * capacity;
         }
+
+        if !was_empty && self.len() == 0 {
+            if let Some(on_empty) = self.on_empty.as_mut() {
+                on_empty();
+            }
+        }
     }
 
     fn wrote(&mut self, amount: usize) {
+        #[cfg(feature = "checksum")]
+        {
+            let (first, second) = self.ranges(self.write_idx, amount);
+            self.write_crc.update(&self.data[first]);
+            self.write_crc.update(&self.data[second]);
+        }
+
+        #[cfg(any(feature = "latency", feature = "rate"))]
+        if let Some(clock) = &self.clock {
+            let now = clock.now();
+            #[cfg(feature = "latency")]
+            self.latency.wrote(now, amount);
+            #[cfg(feature = "rate")]
+            self.write_rate
+                .record(now, clock.ticks_per_second(), amount);
+        }
+
+        let was_full = self.len() == self.data.len();
         self.write_idx += amount;
 
         let capacity = self.data.len();
         if self.write_idx >= 2 * capacity {
             self.write_idx -= 2 * capacity;
         }
+
+        if !was_full && self.len() == capacity {
+            if let Some(on_full) = self.on_full.as_mut() {
+                on_full();
+            }
+        }
+    }
+
+    /// Total number of bytes buffered, including any that wrap around the end of storage.
+    fn len(&self) -> usize {
+        let capacity = self.data.len();
+        let mut write_idx = self.write_idx;
+        if write_idx < self.read_idx {
+            write_idx += 2 * capacity;
+        }
+
+        write_idx - self.read_idx
+    }
+
+    /// Storage ranges covering `len` bytes starting at `idx`: the segment up to the end of
+    /// storage and the wrapped remainder (which may be empty).
+    fn ranges(&self, idx: usize, len: usize) -> (Range<usize>, Range<usize>) {
+        let begin = self.wrap(idx);
+        let first = (self.data.len() - begin).min(len);
+        (begin..begin + first, 0..len - first)
+    }
+
+    /// The buffered bytes in order, split where they wrap around the end of storage.
+    fn as_slices(&self) -> (&[u8], &[u8]) {
+        let (first, second) = self.ranges(self.read_idx, self.len());
+        (&self.data[first], &self.data[second])
     }
 
     fn readable(&self) -> usize {
@@ -77,168 +336,2131 @@ impl RingBuffer {
 
     fn writeable(&self) -> usize {
         let capacity = self.data.len();
-        let mut write_idx = self.write_idx;
-        if write_idx < self.read_idx {
-            write_idx += 2 * capacity;
-        }
-
-        let remaining_space = capacity - (write_idx - self.read_idx);
+        let remaining_space = capacity - self.len();
         let space_before_end = capacity - self.wrap(self.write_idx);
         remaining_space.min(space_before_end)
     }
 
-    fn park(&mut self, waker: &Waker) {
-        self.waker = Some(waker.clone());
+    fn check_writable(&self) -> Result<(), ChannelError> {
+        if self.reader_gone {
+            Err(ChannelError::ReaderGone { written: 0 })
+        } else if self.writer_closed {
+            Err(ChannelError::WriterClosed)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns the ring to its freshly-constructed state, keeping the storage and callbacks.
+    fn reset(&mut self) {
+        self.read_idx = 0;
+        self.write_idx = 0;
+        self.read_waker = None;
+        self.write_waker = None;
+        self.writer_closed_waker = None;
+        self.reader_gone_waker = None;
+        self.reader_gone = false;
+        self.writer_closed = false;
+        self.wrap_splits = 0;
+        #[cfg(feature = "checksum")]
+        {
+            self.write_crc = Crc32::new();
+            self.read_crc = Crc32::new();
+        }
+        #[cfg(feature = "urgent")]
+        {
+            self.urgent = None;
+        }
+        #[cfg(feature = "latency")]
+        {
+            self.latency = LatencyTracker::default();
+        }
+        #[cfg(feature = "rate")]
+        {
+            self.write_rate = RateSampler::default();
+            self.read_rate = RateSampler::default();
+        }
+    }
+
+    #[cfg(feature = "rate")]
+    fn rate(&mut self, read: bool) -> u64 {
+        let clock = match &self.clock {
+            Some(clock) => clock,
+            None => return 0,
+        };
+        let (now, ticks_per_second) = (clock.now(), clock.ticks_per_second());
+        let sampler = if read {
+            &mut self.read_rate
+        } else {
+            &mut self.write_rate
+        };
+        sampler.rate(now, ticks_per_second)
+    }
+
+    fn close(&mut self) {
+        self.writer_closed = true;
+        self.wake_read();
+        if let Some(waker) = self.writer_closed_waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn reader_dropped(&mut self) {
+        self.reader_gone = true;
+        self.wake_write();
+        if let Some(waker) = self.reader_gone_waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// Registers the reader to be woken when data arrives or the writer closes.
+    fn park_read(&mut self, waker: &Waker) {
+        if !self.busy_poll {
+            register(&mut self.read_waker, waker);
+        }
+    }
+
+    /// Registers the writer to be woken when space frees up or the reader goes away.
+    fn park_write(&mut self, waker: &Waker) {
+        if !self.busy_poll {
+            register(&mut self.write_waker, waker);
+        }
+    }
+
+    fn wake_read(&mut self) {
+        if let Some(waker) = self.read_waker.take() {
+            waker.wake();
+        }
     }
 
-    fn wake(&mut self) {
-        if let Some(waker) = self.waker.take() {
+    fn wake_write(&mut self) {
+        if let Some(waker) = self.write_waker.take() {
             waker.wake();
         }
     }
 }
 
+// Spelled out rather than `vec![0; n]` so storage always comes from `alloc::vec::Vec`.
+#[allow(clippy::slow_vector_initialization)]
+fn zeroed(n: usize) -> Vec<u8> {
+    let mut data = Vec::with_capacity(n);
+    data.resize(n, 0);
+    data
+}
+
+/// Stores `waker` in `slot`, skipping the clone if the same task is already registered.
+fn register(slot: &mut Option<Waker>, waker: &Waker) {
+    match slot {
+        Some(parked) if parked.will_wake(waker) => {}
+        _ => *slot = Some(waker.clone()),
+    }
+}
+
+impl Drop for RingBuffer {
+    fn drop(&mut self) {
+        if let Some(pool) = &self.pool {
+            pool.release(core::mem::take(&mut self.data));
+        }
+    }
+}
+
+/// A source of ring storage for [`RingBuffer::with_capacity_pooled`].
+///
+/// Both methods take `&self` so one pool can serve many rings; share it by passing an `Rc`.
+pub trait BufferPool {
+    /// Returns a buffer to use as storage for a ring of capacity `n`. It needn't be empty or the
+    /// right length: the ring resets its contents, so a buffer whose allocation is already at
+    /// least `n` bytes avoids allocating at all.
+    fn acquire(&self, n: usize) -> Vec<u8>;
+
+    /// Takes back a ring's storage once both of its halves have been dropped.
+    fn release(&self, buf: Vec<u8>);
+}
+
+impl<P: BufferPool + ?Sized> BufferPool for Rc<P> {
+    fn acquire(&self, n: usize) -> Vec<u8> {
+        (**self).acquire(n)
+    }
+
+    fn release(&self, buf: Vec<u8>) {
+        (**self).release(buf)
+    }
+}
+
+/// The complete internal state of a ring: storage plus the raw read and write indices, which run
+/// from 0 to twice the capacity.
+///
+/// Wakers, callbacks and the closed/dropped flags aren't part of the snapshot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RingSnapshot {
+    pub data: Vec<u8>,
+    pub read_idx: usize,
+    pub write_idx: usize,
+    pub capacity: usize,
+}
+
+/// Why one of the higher-level helpers on [`Reader`] or [`Writer`] gave up.
+///
+/// The `AsyncRead`/`AsyncWrite` impls report the same conditions as `io::Error`s; this converts
+/// into one (carrying itself as the inner error) for callers that just want to `?` it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelError {
+    /// The reader was dropped. `written` counts the bytes of the current operation that made it
+    /// into the ring first.
+    ReaderGone { written: usize },
+    /// The writer was already closed, so nothing more can be written.
+    WriterClosed,
+}
+
+impl fmt::Display for ChannelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChannelError::ReaderGone { written } => {
+                write!(f, "reader dropped after {} bytes were written", written)
+            }
+            ChannelError::WriterClosed => write!(f, "writer already closed"),
+        }
+    }
+}
+
+impl core::error::Error for ChannelError {}
+
+impl From<ChannelError> for Error {
+    fn from(err: ChannelError) -> Error {
+        let kind = match err {
+            ChannelError::ReaderGone { .. } => ErrorKind::BrokenPipe,
+            ChannelError::WriterClosed => ErrorKind::NotConnected,
+        };
+        Error::new(kind, err)
+    }
+}
+
+/// Why [`RingBuffer::try_with_capacity`] couldn't build a ring.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllocError {
+    /// A ring with no storage can never pass a byte.
+    ZeroCapacity,
+    /// The allocator couldn't provide the storage.
+    OutOfMemory,
+}
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AllocError::ZeroCapacity => write!(f, "ring capacity must be nonzero"),
+            AllocError::OutOfMemory => write!(f, "couldn't allocate ring storage"),
+        }
+    }
+}
+
+impl core::error::Error for AllocError {}
+
 pub struct Reader {
-    rb: Rc<RefCell<RingBuffer>>,
+    rb: Rc<Shared>,
+    max_transfer: usize,
 }
 
-impl AsyncRead for Reader {
-    fn poll_read(
-        self: Pin<&mut Self>,
+impl Reader {
+    fn poll_read_ref(&self, cx: &mut Context, buf: &mut [u8]) -> Poll<Result<usize, Error>> {
+        self.poll_read_with(cx, buf.len(), |src| buf[..src.len()].copy_from_slice(src))
+    }
+
+    /// Hands `copy` up to `len` contiguous readable bytes and consumes them, or parks.
+    fn poll_read_with(
+        &self,
         cx: &mut Context,
-        buf: &mut [u8],
+        len: usize,
+        copy: impl FnOnce(&[u8]),
     ) -> Poll<Result<usize, Error>> {
+        if len == 0 {
+            return Poll::Ready(Ok(0));
+        }
+
         let mut rb = self.rb.borrow_mut();
-        let n = rb.readable().min(buf.len());
+        let n = rb.readable().min(len).min(self.max_transfer);
         if n > 0 {
             let begin = rb.wrap(rb.read_idx);
             let end = begin + n;
-            buf[..n].copy_from_slice(&rb.data.as_slice()[begin..end]);
+            copy(&rb.data[begin..end]);
             rb.read(n);
-            rb.wake();
+            rb.wake_write();
             Poll::Ready(Ok(n))
+        } else if rb.writer_closed {
+            Poll::Ready(Ok(0))
         } else {
-            rb.park(cx.waker());
+            rb.park_read(cx.waker());
             Poll::Pending
         }
     }
 }
 
-pub struct Writer {
-    rb: Rc<RefCell<RingBuffer>>,
+/// The state both halves point at: the ring, plus any close or drop that happened while the ring
+/// was borrowed and so couldn't be applied yet. Those are applied by the next borrow once the
+/// ring is free, so whichever half is left sees them on its next call.
+struct Shared {
+    ring: RefCell<RingBuffer>,
+    pending_close: Cell<bool>,
+    pending_gone: Cell<bool>,
 }
 
-impl AsyncWrite for Writer {
-    fn poll_write(
-        self: Pin<&mut Self>,
-        cx: &mut Context,
-        buf: &[u8],
-    ) -> Poll<Result<usize, Error>> {
-        let mut rb = self.rb.borrow_mut();
-        let n = rb.writeable().min(buf.len());
-        if n > 0 {
-            let begin = rb.wrap(rb.write_idx);
-            let end = begin + n;
-            rb.data.as_mut_slice()[begin..end].copy_from_slice(&buf[..n]);
-            rb.wrote(n);
-            rb.wake();
-            Poll::Ready(Ok(n))
-        } else {
-            rb.park(cx.waker());
-            Poll::Pending
+impl Shared {
+    fn new(ring: RingBuffer) -> Shared {
+        Shared {
+            ring: RefCell::new(ring),
+            pending_close: Cell::new(false),
+            pending_gone: Cell::new(false),
         }
     }
 
-    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), Error>> {
-        Poll::Ready(Ok(()))
+    /// Applies a deferred close or drop, if there is one and the ring is free now.
+    fn settle(&self) {
+        if !self.pending_close.get() && !self.pending_gone.get() {
+            return;
+        }
+        if let Ok(mut rb) = self.ring.try_borrow_mut() {
+            if self.pending_close.take() {
+                rb.close();
+            }
+            if self.pending_gone.take() {
+                rb.reader_dropped();
+            }
+        }
     }
 
-    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), Error>> {
-        Poll::Ready(Ok(()))
+    // These shadow the `RefCell` methods reached through `Deref`, so every entry point on either
+    // half settles first without having to remember to.
+    fn borrow(&self) -> Ref<'_, RingBuffer> {
+        self.settle();
+        self.ring.borrow()
+    }
+
+    fn borrow_mut(&self) -> RefMut<'_, RingBuffer> {
+        self.settle();
+        self.ring.borrow_mut()
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use proptest::prelude::*;
-    use proptest_derive::Arbitrary;
+impl Deref for Shared {
+    type Target = RefCell<RingBuffer>;
 
-    /// Anything you can do to a RingBuffer.
-    #[derive(Debug, Arbitrary)]
-    enum Operation {
-        Write(Vec<u8>), // amount to write
-        Read(u8),       // buffer size to use in read
+    fn deref(&self) -> &RefCell<RingBuffer> {
+        &self.ring
     }
+}
 
-    /// A very simple 'oracle' implementation.
-    struct Model {
-        capacity: usize,
-        data: Vec<u8>,
+/// Borrowed view of readable bytes from [`Reader::poll_fill_buf`].
+///
+/// The ring stays borrowed for as long as the guard is alive, and using either half meanwhile
+/// panics. That includes the [`Writer`] being polled by another task on the same executor, so
+/// don't hold a guard across an `.await`. Dropping the writer is the exception: the reader sees
+/// the close once the guard goes.
+pub struct ReadGuard<'a> {
+    data: Option<Ref<'a, [u8]>>,
+    shared: &'a Shared,
+}
+
+impl Deref for ReadGuard<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.data
+            .as_ref()
+            .expect("guard data is only taken on drop")
     }
+}
 
-    impl Model {
-        fn new(capacity: usize) -> Model {
-            Model {
-                capacity,
-                data: Vec::new(),
-            }
-        }
+impl Drop for ReadGuard<'_> {
+    fn drop(&mut self) {
+        self.data = None;
+        self.shared.settle();
+    }
+}
 
-        fn is_empty(&self) -> bool {
-            self.data.is_empty()
+impl Drop for Reader {
+    fn drop(&mut self) {
+        match self.rb.try_borrow_mut() {
+            Ok(mut rb) => rb.reader_dropped(),
+            Err(_) => self.rb.pending_gone.set(true),
         }
+    }
+}
 
-        fn is_full(&self) -> bool {
-            self.data.len() == self.capacity
-        }
+/// Readers are equal when their rings hold the same readable bytes, regardless of capacity or
+/// where in storage those bytes happen to sit.
+impl PartialEq for Reader {
+    fn eq(&self, other: &Reader) -> bool {
+        let (ours, theirs) = (self.rb.borrow(), other.rb.borrow());
+        let (a, b) = ours.as_slices();
+        let (c, d) = theirs.as_slices();
+        ours.len() == theirs.len() && a.iter().chain(b).eq(c.iter().chain(d))
+    }
+}
 
-        fn write(&mut self, bytes: &[u8]) -> usize {
-            let before = self.data.len();
+impl Eq for Reader {}
 
-            self.data.extend_from_slice(&bytes);
-            self.data.resize(self.capacity.min(self.data.len()), 0);
+/// True when the readable bytes, read out in order, are exactly `other`.
+impl PartialEq<[u8]> for Reader {
+    fn eq(&self, other: &[u8]) -> bool {
+        let rb = self.rb.borrow();
+        let (a, b) = rb.as_slices();
+        rb.len() == other.len() && a.iter().chain(b).eq(other)
+    }
+}
 
-            self.data.len() - before
-        }
+impl Reader {
+    /// Number of bytes buffered and ready to read.
+    pub fn len(&self) -> usize {
+        self.rb.borrow().len()
+    }
 
-        fn read(&mut self, n: usize) -> Vec<u8> {
-            self.data.drain(..n.min(self.data.len())).collect()
-        }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 
-    proptest! {
-        #[test]
-        fn it_works(capacity in any::<u8>(),
-                    operations in any::<Vec<Operation>>()) {
-            let capacity = capacity as usize;
-            let mut model = Model::new(capacity);
-            let (mut tx, mut rx) = RingBuffer::with_capacity(capacity);
+    /// Waits until there is data to read, or the writer has closed and the next read will report
+    /// EOF. Nothing is consumed.
+    pub async fn readable(&self) -> Result<(), ChannelError> {
+        future::poll_fn(|cx| {
+            let mut rb = self.rb.borrow_mut();
+            if rb.len() > 0 || rb.writer_closed {
+                Poll::Ready(Ok(()))
+            } else {
+                rb.park_read(cx.waker());
+                Poll::Pending
+            }
+        })
+        .await
+    }
 
-            for op in operations {
-                match op {
-                    Operation::Write(data) => {
-                        let written = tx.write(&data).now_or_never().unwrap_or(Ok(0)).expect("can't fail");
+    /// Like `poll_read`, but into uninitialized memory, so the caller needn't zero `buf` first.
+    ///
+    /// On `Ready(Ok(n))` the first `n` elements of `buf` have been initialized; the rest are left
+    /// untouched.
+    pub fn poll_read_buf(
+        &self,
+        cx: &mut Context,
+        buf: &mut [MaybeUninit<u8>],
+    ) -> Poll<Result<usize, Error>> {
+        self.poll_read_with(cx, buf.len(), |src| {
+            for (dst, &b) in buf.iter_mut().zip(src) {
+                dst.write(b);
+            }
+        })
+    }
 
-                        // We might have written less than the full buffer due to wrapping, but we
-                        // should make *some* progress as long as there's space.
-                        prop_assert!(model.is_full() || data.is_empty() || written > 0);
-                        prop_assert_eq!(model.write(&data[..written]), written);
-                    }
+    /// How many transfers straddled the end of storage and so took two copies instead of one:
+    /// reads through `read_until`, `read_exact_vectored` or `read_bytes`, and vectored writes.
+    /// If it's high relative to traffic, a different capacity or write size may line up better.
+    pub fn wrap_splits(&self) -> u64 {
+        self.rb.borrow().wrap_splits
+    }
 
-                    Operation::Read(n) => {
-                        let n = n as usize;
-                        let mut buf = [0; 256];
-                        let nread = rx.read(&mut buf[..n]).now_or_never().unwrap_or(Ok(0)).expect("can't fail");
+    /// Waits until the writer has closed or been dropped. Bytes it wrote may still be buffered.
+    pub async fn wait_closed(&self) {
+        future::poll_fn(|cx| self.poll_closed(cx)).await
+    }
 
-                        // We might have read less than the full buffer due to wrapping, but we
-                        // should make *some* progress as long as there's some data.
-                        prop_assert!(model.is_empty() || n == 0 || nread > 0);
-                        prop_assert_eq!(model.read(nread), &buf[..nread]);
-                    }
-                }
+    /// Ready once the writer has closed or been dropped, even if bytes remain to be read;
+    /// otherwise registers `cx` to be woken when it does.
+    ///
+    /// This uses its own waker slot, so it doesn't displace a task parked in a read.
+    pub fn poll_closed(&self, cx: &mut Context) -> Poll<()> {
+        let rb = &mut *self.rb.borrow_mut();
+        if rb.writer_closed {
+            Poll::Ready(())
+        } else {
+            if !rb.busy_poll {
+                register(&mut rb.writer_closed_waker, cx.waker());
             }
+            Poll::Pending
+        }
+    }
+
+    /// Waits for data, then returns the contiguous run of readable bytes without copying them.
+    ///
+    /// Only one segment is exposed at a time: if the buffered data wraps around the end of
+    /// storage, the guard covers the part up to the end and the rest is available from another
+    /// fill once the first part has been [consumed](Reader::consume). At EOF the guard is empty.
+    pub fn poll_fill_buf(&self, cx: &mut Context) -> Poll<Result<ReadGuard<'_>, Error>> {
+        let mut rb = self.rb.borrow_mut();
+        if rb.readable() == 0 && !rb.writer_closed {
+            rb.park_read(cx.waker());
+            return Poll::Pending;
+        }
+        drop(rb);
+
+        let data = Ref::map(self.rb.borrow(), |rb| {
+            let begin = rb.wrap(rb.read_idx);
+            &rb.data[begin..begin + rb.readable()]
+        });
+        Poll::Ready(Ok(ReadGuard {
+            data: Some(data),
+            shared: &self.rb,
+        }))
+    }
+
+    /// Marks `n` bytes from the front of the buffer as read, e.g. after inspecting them through
+    /// [`Reader::poll_fill_buf`]. Anything beyond what's buffered is ignored.
+    pub fn consume(&self, n: usize) {
+        self.discard(n);
+    }
 
+    /// Drops up to `n` buffered bytes without copying them anywhere, returning how many were
+    /// dropped. Doesn't wait for more.
+    pub fn discard(&self, n: usize) -> usize {
+        let mut rb = self.rb.borrow_mut();
+        let n = n.min(rb.len());
+        if n > 0 {
+            rb.read(n);
+            rb.wake_write();
+        }
+        n
+    }
+
+    /// Drops the next `n` bytes, waiting for them to arrive as needed.
+    ///
+    /// Returns the number of bytes skipped, which is less than `n` only at EOF.
+    pub async fn skip(&self, n: usize) -> Result<usize, ChannelError> {
+        let mut skipped = 0;
+        future::poll_fn(|cx| {
+            skipped += self.discard(n - skipped);
+            let mut rb = self.rb.borrow_mut();
+            if skipped == n || rb.writer_closed {
+                Poll::Ready(Ok(skipped))
+            } else {
+                rb.park_read(cx.waker());
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// Appends bytes to `out` up to and including the first `delim`, waiting for more data as
+    /// needed.
+    ///
+    /// Returns the number of bytes appended, which is 0 only at EOF. If the writer closes before a
+    /// delimiter arrives, whatever was buffered is appended without one.
+    pub async fn read_until(&self, delim: u8, out: &mut Vec<u8>) -> Result<usize, ChannelError> {
+        let mut total = 0;
+        future::poll_fn(|cx| {
+            let mut rb = self.rb.borrow_mut();
+            let (first, second) = rb.as_slices();
+            let (n, found) = match first.iter().chain(second).position(|&b| b == delim) {
+                Some(idx) => (idx + 1, true),
+                None => (rb.len(), false),
+            };
+
+            let split = n.min(first.len());
+            out.extend_from_slice(&first[..split]);
+            out.extend_from_slice(&second[..n - split]);
+            if n > split {
+                rb.wrap_splits += 1;
+            }
+            total += n;
+            if n > 0 {
+                rb.read(n);
+                rb.wake_write();
+            }
+
+            if found || rb.writer_closed {
+                Poll::Ready(Ok(total))
+            } else {
+                rb.park_read(cx.waker());
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// Waits until at least `n` bytes are buffered, or the writer has closed, then copies up to
+    /// `out.len()` of them into `out` without consuming anything.
+    ///
+    /// Returns the number of bytes copied: `out.len()` or the number buffered, whichever is
+    /// smaller. That can be less than `n` if `out` is short or the writer has closed. An `n`
+    /// beyond the ring's capacity could never be met, so it waits for a full ring instead.
+    pub async fn peek_at_least(&self, n: usize, out: &mut [u8]) -> Result<usize, ChannelError> {
+        future::poll_fn(|cx| {
+            let mut rb = self.rb.borrow_mut();
+            let len = rb.len();
+            if len < n.min(rb.data.len()) && !rb.writer_closed {
+                rb.park_read(cx.waker());
+                return Poll::Pending;
+            }
+
+            let (first, second) = rb.as_slices();
+            let mut copied = 0;
+            for (dst, &b) in out.iter_mut().zip(first.iter().chain(second)) {
+                *dst = b;
+                copied += 1;
+            }
+            Poll::Ready(Ok(copied))
+        })
+        .await
+    }
+
+    /// Fills every slice of `bufs` in order, waiting for more data as needed. `bufs` is advanced
+    /// as it goes, so its contents are unspecified afterwards.
+    ///
+    /// Returns the number of bytes read, which falls short of the slices' total length only if
+    /// the writer closed first.
+    pub async fn read_exact_vectored(
+        &self,
+        mut bufs: &mut [IoSliceMut<'_>],
+    ) -> Result<usize, ChannelError> {
+        let mut total = 0;
+        IoSliceMut::advance_slices(&mut bufs, 0);
+        future::poll_fn(|cx| {
+            let mut rb = self.rb.borrow_mut();
+            let (first, second) = rb.as_slices();
+            let first_len = first.len();
+            let mut src = first.iter().chain(second);
+            let mut n = 0;
+            for buf in bufs.iter_mut() {
+                let mut copied = 0;
+                for (dst, &b) in buf.iter_mut().zip(&mut src) {
+                    *dst = b;
+                    copied += 1;
+                }
+                n += copied;
+                if copied < buf.len() {
+                    break;
+                }
+            }
+
+            if n > first_len {
+                rb.wrap_splits += 1;
+            }
+            if n > 0 {
+                rb.read(n);
+                rb.wake_write();
+                total += n;
+                IoSliceMut::advance_slices(&mut bufs, n);
+            }
+
+            if bufs.is_empty() || rb.writer_closed {
+                Poll::Ready(Ok(total))
+            } else {
+                rb.park_read(cx.waker());
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// Takes up to `max` buffered bytes, across the wrap point if need be, as one freshly
+    /// allocated `Bytes`. Doesn't wait: if nothing is buffered the result is empty.
+    #[cfg(feature = "bytes")]
+    pub fn read_bytes(&self, max: usize) -> bytes::Bytes {
+        let mut rb = self.rb.borrow_mut();
+        let n = rb.len().min(max);
+        let (first, second) = rb.as_slices();
+        let mut out = Vec::with_capacity(n);
+        let split = n.min(first.len());
+        out.extend_from_slice(&first[..split]);
+        out.extend_from_slice(&second[..n - split]);
+        if n > split {
+            rb.wrap_splits += 1;
+        }
+        if n > 0 {
+            rb.read(n);
+            rb.wake_write();
+        }
+        out.into()
+    }
+
+    /// Forwards bytes into `writer` up to and including the first `delim`, waiting for more data
+    /// as needed, then flushes `writer`. Bytes after the delimiter stay in the ring.
+    ///
+    /// Returns the number of bytes forwarded. If the writer closes before a delimiter arrives,
+    /// whatever was buffered is forwarded without one. As with [`fast_copy`], bytes go straight
+    /// from storage into `writer`, which therefore must not be this ring's [`Writer`].
+    pub async fn copy_until<W>(&self, delim: u8, writer: &mut W) -> Result<u64, Error>
+    where
+        W: AsyncWrite + Unpin + ?Sized,
+    {
+        forward(self, Some(delim), writer).await
+    }
+
+    /// Creates an independent ring of the same capacity, pre-filled with a copy of the bytes this
+    /// reader would see next. This reader's position is untouched.
+    pub fn duplicate(&self) -> (Writer, Reader) {
+        let rb = self.rb.borrow();
+        let (first, second) = rb.as_slices();
+
+        let mut data = Vec::with_capacity(rb.data.len());
+        data.extend_from_slice(first);
+        data.extend_from_slice(second);
+        data.resize(rb.data.len(), 0);
+
+        let mut copy = RingBuffer::new(data);
+        copy.write_idx = rb.len();
+        copy.split()
+    }
+
+    /// Reuses the ring for a new exchange once the current one is over, so the same halves can
+    /// carry another stream without reallocating.
+    ///
+    /// This only happens when both halves agree the stream is finished: the writer is still alive
+    /// but has been closed, and everything it wrote has been read. Returns whether the reset took
+    /// place. Any parked wakers are discarded, so there must be no reads or writes in flight on
+    /// either half (e.g. a read future still pending in another task) when this is called.
+    pub fn reset_for_reuse(&self) -> bool {
+        let mut rb = self.rb.borrow_mut();
+        let writer_alive = Rc::strong_count(&self.rb) == 2;
+        if writer_alive && rb.writer_closed && rb.len() == 0 {
+            rb.reset();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Caps how many bytes a single `poll_read` hands back, to bound the work a consumer does per
+    /// wakeup. `None`, the default, reads as much as is available and fits.
+    pub fn set_max_transfer_per_poll(&mut self, max: Option<NonZeroUsize>) {
+        self.max_transfer = max.map_or(usize::MAX, NonZeroUsize::get);
+    }
+
+    /// Raw `(read_idx, write_idx, capacity)`, for troubleshooting wrap behavior. Not part of the
+    /// stable API.
+    #[doc(hidden)]
+    pub fn debug_indices(&self) -> (usize, usize, usize) {
+        let rb = self.rb.borrow();
+        (rb.read_idx, rb.write_idx, rb.data.len())
+    }
+
+    /// Captures the storage and indices exactly, for [`RingBuffer::restore`].
+    pub fn snapshot(&self) -> RingSnapshot {
+        self.rb.borrow().snapshot()
+    }
+
+    /// Copies the bytes currently waiting to be read into a shared, immutable buffer, without
+    /// consuming them. The copy is detached from the ring, so it can be handed to a monitor and
+    /// inspected at leisure while the halves carry on.
+    pub fn cow_snapshot(&self) -> Arc<[u8]> {
+        let rb = self.rb.borrow();
+        let (first, second) = rb.as_slices();
+        first.iter().chain(second).copied().collect()
+    }
+
+    /// Calls `f` each time a read drains the ring, i.e. on the transition into the empty state.
+    ///
+    /// `f` runs while the ring is borrowed, so it must not use either half, though dropping one
+    /// is fine.
+    pub fn on_empty(&self, f: impl FnMut() + 'static) {
+        self.rb.borrow_mut().on_empty = Some(Box::new(f));
+    }
+
+    /// Takes the pending out-of-band byte sent with [`Writer::send_urgent`], if any.
+    #[cfg(feature = "urgent")]
+    pub fn take_urgent(&self) -> Option<u8> {
+        self.rb.borrow_mut().urgent.take()
+    }
+
+    /// Bytes/second read over the last second. Always 0 unless the ring was built with
+    /// [`RingBuffer::with_clock`].
+    #[cfg(feature = "rate")]
+    pub fn read_rate(&self) -> u64 {
+        self.rb.borrow_mut().rate(true)
+    }
+
+    /// Write-to-read latency so far, which is all zeros unless the ring was built with
+    /// [`RingBuffer::with_clock`], plus the [wrap split](Reader::wrap_splits) count.
+    #[cfg(feature = "latency")]
+    pub fn stats(&self) -> Stats {
+        let rb = self.rb.borrow();
+        Stats {
+            wrap_splits: rb.wrap_splits,
+            ..rb.latency.stats()
+        }
+    }
+
+    /// CRC-32 of every byte consumed from the ring so far.
+    #[cfg(feature = "checksum")]
+    pub fn read_checksum(&self) -> u32 {
+        self.rb.borrow().read_crc.value()
+    }
+}
+
+impl AsyncRead for Reader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, Error>> {
+        self.poll_read_ref(cx, buf)
+    }
+}
+
+/// Lets a reader be polled through a shared reference, e.g. from a structure held in an `Rc`.
+/// `Reader` isn't `Send`, so this never crosses threads; the ring's `RefCell` serializes each
+/// call.
+impl AsyncRead for &Reader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, Error>> {
+        self.poll_read_ref(cx, buf)
+    }
+}
+
+pub struct Writer {
+    rb: Rc<Shared>,
+    max_transfer: usize,
+}
+
+impl Writer {
+    /// Writes all of `buf`, waiting for space as needed and continuing across the wrap point.
+    ///
+    /// If the reader is dropped first, the error records how much of `buf` made it into the ring.
+    pub async fn write_all(&self, buf: &[u8]) -> Result<(), ChannelError> {
+        let mut written = 0;
+        future::poll_fn(|cx| {
+            while written < buf.len() {
+                match ready!(self.poll_write_ref(cx, &buf[written..])) {
+                    Ok(n) => written += n,
+                    Err(ChannelError::ReaderGone { .. }) => {
+                        return Poll::Ready(Err(ChannelError::ReaderGone { written }));
+                    }
+                    Err(e) => return Poll::Ready(Err(e)),
+                }
+            }
+            Poll::Ready(Ok(()))
+        })
+        .await
+    }
+
+    /// Writes every slice of `bufs` in order, waiting for space as needed. `bufs` is advanced as
+    /// it goes, so its contents are unspecified afterwards.
+    ///
+    /// If the reader is dropped first, the error records how many bytes made it into the ring.
+    pub async fn write_all_vectored(
+        &self,
+        mut bufs: &mut [IoSlice<'_>],
+    ) -> Result<(), ChannelError> {
+        let mut written = 0;
+        IoSlice::advance_slices(&mut bufs, 0);
+        future::poll_fn(|cx| {
+            while !bufs.is_empty() {
+                match ready!(self.poll_write_vectored_ref(cx, bufs)) {
+                    Ok(n) => {
+                        written += n;
+                        IoSlice::advance_slices(&mut bufs, n);
+                    }
+                    Err(ChannelError::ReaderGone { .. }) => {
+                        return Poll::Ready(Err(ChannelError::ReaderGone { written }));
+                    }
+                    Err(e) => return Poll::Ready(Err(e)),
+                }
+            }
+            Poll::Ready(Ok(()))
+        })
+        .await
+    }
+
+    /// Waits until there is space to write into.
+    pub async fn writable(&self) -> Result<(), ChannelError> {
+        future::poll_fn(|cx| {
+            let mut rb = self.rb.borrow_mut();
+            if let Err(e) = rb.check_writable() {
+                Poll::Ready(Err(e))
+            } else if rb.writeable() > 0 {
+                Poll::Ready(Ok(()))
+            } else {
+                rb.park_write(cx.waker());
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// Waits until the reader has been dropped.
+    pub async fn wait_closed(&self) {
+        future::poll_fn(|cx| self.poll_closed(cx)).await
+    }
+
+    /// Ready once the reader has been dropped; otherwise registers `cx` to be woken when it is.
+    ///
+    /// This uses its own waker slot, so it can be polled from a different task than the one
+    /// writing.
+    pub fn poll_closed(&self, cx: &mut Context) -> Poll<()> {
+        let rb = &mut *self.rb.borrow_mut();
+        if rb.reader_gone {
+            Poll::Ready(())
+        } else {
+            if !rb.busy_poll {
+                register(&mut rb.reader_gone_waker, cx.waker());
+            }
+            Poll::Pending
+        }
+    }
+
+    /// Caps how many bytes a single `poll_write` accepts, to bound the work a producer does per
+    /// call. `None`, the default, writes as much as there is room for.
+    pub fn set_max_transfer_per_poll(&mut self, max: Option<NonZeroUsize>) {
+        self.max_transfer = max.map_or(usize::MAX, NonZeroUsize::get);
+    }
+
+    /// Calls `f` each time a write fills the ring, i.e. on the transition into the full state.
+    ///
+    /// `f` runs while the ring is borrowed, so it must not use either half, though dropping one
+    /// is fine.
+    pub fn on_full(&self, f: impl FnMut() + 'static) {
+        self.rb.borrow_mut().on_full = Some(Box::new(f));
+    }
+
+    /// Sends a single out-of-band byte that bypasses the ring, replacing any the reader hasn't
+    /// taken yet, and wakes the reader.
+    ///
+    /// The byte is only delivered through [`Reader::take_urgent`]; a reader parked in a normal
+    /// read is woken but will go back to waiting if no data arrived.
+    #[cfg(feature = "urgent")]
+    pub fn send_urgent(&self, b: u8) {
+        let mut rb = self.rb.borrow_mut();
+        rb.urgent = Some(b);
+        rb.wake_read();
+    }
+
+    /// Bytes/second written over the last second. Always 0 unless the ring was built with
+    /// [`RingBuffer::with_clock`].
+    #[cfg(feature = "rate")]
+    pub fn write_rate(&self) -> u64 {
+        self.rb.borrow_mut().rate(false)
+    }
+
+    /// CRC-32 of every byte written into the ring so far.
+    #[cfg(feature = "checksum")]
+    pub fn write_checksum(&self) -> u32 {
+        self.rb.borrow().write_crc.value()
+    }
+
+    fn poll_write_ref(&self, cx: &mut Context, buf: &[u8]) -> Poll<Result<usize, ChannelError>> {
+        let mut rb = self.rb.borrow_mut();
+        rb.check_writable()?;
+
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        let n = rb.writeable().min(buf.len()).min(self.max_transfer);
+        if n > 0 {
+            let begin = rb.wrap(rb.write_idx);
+            let end = begin + n;
+            rb.data[begin..end].copy_from_slice(&buf[..n]);
+            rb.wrote(n);
+            rb.wake_read();
+            Poll::Ready(Ok(n))
+        } else {
+            rb.park_write(cx.waker());
+            Poll::Pending
+        }
+    }
+
+    /// Copies as much of `bufs` as fits, in order, filling space on both sides of the wrap point
+    /// in one call.
+    fn poll_write_vectored_ref(
+        &self,
+        cx: &mut Context,
+        bufs: &[IoSlice],
+    ) -> Poll<Result<usize, ChannelError>> {
+        let mut rb = self.rb.borrow_mut();
+        rb.check_writable()?;
+
+        if bufs.iter().all(|buf| buf.is_empty()) {
+            return Poll::Ready(Ok(0));
+        }
+
+        let mut written = 0;
+        'bufs: for whole in bufs {
+            let mut buf = &whole[..];
+            while !buf.is_empty() {
+                let n = rb
+                    .writeable()
+                    .min(buf.len())
+                    .min(self.max_transfer - written);
+                if n == 0 {
+                    break 'bufs;
+                }
+                let begin = rb.wrap(rb.write_idx);
+                if buf.len() < whole.len() {
+                    // Picking this slice up again at the front of storage.
+                    rb.wrap_splits += 1;
+                }
+                rb.data[begin..begin + n].copy_from_slice(&buf[..n]);
+                rb.wrote(n);
+                written += n;
+                buf = &buf[n..];
+            }
+        }
+
+        if written > 0 {
+            rb.wake_read();
+            Poll::Ready(Ok(written))
+        } else {
+            rb.park_write(cx.waker());
+            Poll::Pending
+        }
+    }
+
+    /// Bytes are visible to the reader as soon as they're written, so there's nothing to flush.
+    fn poll_flush_ref(&self, _cx: &mut Context) -> Poll<Result<(), Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    /// Flushes, then marks the stream closed so the reader sees EOF once it has drained the ring.
+    fn poll_close_ref(&self, cx: &mut Context) -> Poll<Result<(), Error>> {
+        ready!(self.poll_flush_ref(cx))?;
+        self.rb.borrow_mut().close();
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for Writer {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        self.poll_write_ref(cx, buf).map_err(Error::from)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        bufs: &[IoSlice],
+    ) -> Poll<Result<usize, Error>> {
+        self.poll_write_vectored_ref(cx, bufs).map_err(Error::from)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Error>> {
+        self.poll_flush_ref(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Error>> {
+        self.poll_close_ref(cx)
+    }
+}
+
+/// Lets several pieces of code in the same task share one writer without `&mut`. `Writer` isn't
+/// `Send`, so this never crosses threads; the ring's `RefCell` serializes each call.
+///
+/// Writes from different sharers interleave at `poll_write` granularity, and closing through any
+/// of them closes the stream for all.
+impl AsyncWrite for &Writer {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        self.poll_write_ref(cx, buf).map_err(Error::from)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        bufs: &[IoSlice],
+    ) -> Poll<Result<usize, Error>> {
+        self.poll_write_vectored_ref(cx, bufs).map_err(Error::from)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Error>> {
+        self.poll_flush_ref(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Error>> {
+        self.poll_close_ref(cx)
+    }
+}
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        match self.rb.try_borrow_mut() {
+            Ok(mut rb) => rb.close(),
+            // A ReadGuard is alive; it finishes the close when it goes.
+            Err(_) => self.rb.pending_close.set(true),
+        }
+    }
+}
+
+/// One end of a [`RingBuffer::duplex`] pair: reads come from the other end's writes, and
+/// closing shuts down only this end's outgoing direction.
+pub struct Duplex {
+    writer: Writer,
+    reader: Reader,
+}
+
+impl Duplex {
+    /// Separates the two directions, e.g. to hand them to different parts of a program.
+    pub fn into_split(self) -> (Writer, Reader) {
+        (self.writer, self.reader)
+    }
+}
+
+impl AsyncRead for Duplex {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, Error>> {
+        self.reader.poll_read_ref(cx, buf)
+    }
+}
+
+impl AsyncWrite for Duplex {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        self.writer.poll_write_ref(cx, buf).map_err(Error::from)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        bufs: &[IoSlice],
+    ) -> Poll<Result<usize, Error>> {
+        self.writer
+            .poll_write_vectored_ref(cx, bufs)
+            .map_err(Error::from)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.get_mut().writer).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.get_mut().writer).poll_close(cx)
+    }
+}
+
+/// Copies everything from `reader` into `writer` until EOF, then flushes `writer`.
+///
+/// Unlike `futures::io::copy` there's no intermediate buffer: bytes go straight from the ring's
+/// storage into `writer`, and each poll keeps copying until the ring is empty or `writer` pushes
+/// back. The ring's writer is woken once per poll rather than after every chunk.
+///
+/// `writer` must not be the [`Writer`] for this same ring; the ring stays borrowed while
+/// `writer` is being polled.
+pub async fn fast_copy<W>(reader: &Reader, writer: &mut W) -> Result<u64, Error>
+where
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    forward(reader, None, writer).await
+}
+
+/// The loop behind [`fast_copy`] and [`Reader::copy_until`]: writes straight from storage into
+/// `writer` until EOF or, given a `delim`, until one has been written, then flushes `writer`.
+async fn forward<W>(reader: &Reader, delim: Option<u8>, writer: &mut W) -> Result<u64, Error>
+where
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    let mut total = 0;
+    let mut done = false;
+    future::poll_fn(|cx| {
+        let mut rb = reader.rb.borrow_mut();
+        let mut consumed = false;
+        let result = loop {
+            if done {
+                break Poll::Ready(Ok(()));
+            }
+            let n = rb.readable();
+            if n == 0 {
+                if rb.writer_closed {
+                    done = true;
+                    continue;
+                }
+                rb.park_read(cx.waker());
+                break Poll::Pending;
+            }
+
+            let begin = rb.wrap(rb.read_idx);
+            let chunk = &rb.data[begin..begin + n];
+            let (len, found) = match delim.and_then(|d| chunk.iter().position(|&b| b == d)) {
+                Some(idx) => (idx + 1, true),
+                None => (n, false),
+            };
+            match Pin::new(&mut *writer).poll_write(cx, &chunk[..len]) {
+                Poll::Ready(Ok(0)) => break Poll::Ready(Err(ErrorKind::WriteZero.into())),
+                Poll::Ready(Ok(written)) => {
+                    rb.read(written);
+                    total += written as u64;
+                    consumed = true;
+                    done = found && written == len;
+                }
+                Poll::Ready(Err(e)) => break Poll::Ready(Err(e)),
+                Poll::Pending => break Poll::Pending,
+            }
+        };
+
+        if consumed {
+            rb.wake_write();
+        }
+        drop(rb);
+
+        ready!(result)?;
+        ready!(Pin::new(&mut *writer).poll_flush(cx))?;
+        Poll::Ready(Ok(total))
+    })
+    .await
+}
+
+#[cfg(test)]
+#[allow(non_local_definitions)] // proptest-derive 0.1's expansion trips this lint.
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use proptest_derive::Arbitrary;
+
+    /// Anything you can do to a RingBuffer.
+    #[derive(Debug, Arbitrary)]
+    enum Operation {
+        Write(Vec<u8>), // amount to write
+        Read(u8),       // buffer size to use in read
+    }
+
+    /// A very simple 'oracle' implementation.
+    struct Model {
+        capacity: usize,
+        data: Vec<u8>,
+    }
+
+    impl Model {
+        fn new(capacity: usize) -> Model {
+            Model {
+                capacity,
+                data: Vec::new(),
+            }
+        }
+
+        fn is_empty(&self) -> bool {
+            self.data.is_empty()
+        }
+
+        fn is_full(&self) -> bool {
+            self.data.len() == self.capacity
+        }
+
+        fn write(&mut self, bytes: &[u8]) -> usize {
+            let before = self.data.len();
+
+            self.data.extend_from_slice(bytes);
+            self.data.resize(self.capacity.min(self.data.len()), 0);
+
+            self.data.len() - before
+        }
+
+        fn read(&mut self, n: usize) -> Vec<u8> {
+            self.data.drain(..n.min(self.data.len())).collect()
+        }
+
+        /// A reader holding the same bytes, laid out from the start of storage.
+        fn to_reader(&self) -> Reader {
+            let (tx, rx) = RingBuffer::with_capacity(self.capacity);
+            tx.write_all(&self.data).now_or_never().unwrap().unwrap();
+            rx
+        }
+    }
+
+    #[test]
+    fn empty_read_completes() {
+        let (_tx, mut rx) = RingBuffer::with_capacity(4);
+        let result = rx.read(&mut []).now_or_never().expect("shouldn't park");
+        assert_eq!(result.expect("can't fail"), 0);
+    }
+
+    #[test]
+    fn empty_write_completes() {
+        let (mut tx, _rx) = RingBuffer::with_capacity(4);
+        tx.write_all(&[1, 2, 3, 4]).now_or_never().unwrap().unwrap();
+        let result = tx.write(&[]).now_or_never().expect("shouldn't park");
+        assert_eq!(result.expect("can't fail"), 0);
+    }
+
+    #[cfg(feature = "checksum")]
+    #[test]
+    fn checksums_track_throughput() {
+        let (mut tx, mut rx) = RingBuffer::with_capacity(4);
+        let mut input = &b"123456789"[..];
+        let mut output = Vec::new();
+        while !input.is_empty() {
+            let n = tx.write(input).now_or_never().unwrap().unwrap();
+            input = &input[n..];
+
+            let mut buf = [0; 4];
+            let n = rx.read(&mut buf).now_or_never().unwrap().unwrap();
+            output.extend_from_slice(&buf[..n]);
+        }
+
+        // Drain whatever wrapped.
+        let mut buf = [0; 4];
+        while let Some(Ok(n)) = rx.read(&mut buf).now_or_never() {
+            output.extend_from_slice(&buf[..n]);
+        }
+
+        assert_eq!(output, b"123456789");
+        assert_eq!(tx.write_checksum(), 0xCBF4_3926);
+        assert_eq!(rx.read_checksum(), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn write_all_reports_reader_drop() {
+        let (tx, rx) = RingBuffer::with_capacity(4);
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+
+        let mut write = Box::pin(tx.write_all(&[1, 2, 3, 4, 5, 6]));
+        assert!(write.as_mut().poll(&mut cx).is_pending());
+
+        drop(rx);
+        let result = write.as_mut().poll(&mut cx);
+        assert_eq!(
+            result,
+            Poll::Ready(Err(ChannelError::ReaderGone { written: 4 }))
+        );
+    }
+
+    #[test]
+    fn write_after_close_fails() {
+        let (mut tx, _rx) = RingBuffer::with_capacity(4);
+        tx.close().now_or_never().unwrap().unwrap();
+
+        let result = tx.write_all(&[1]).now_or_never().unwrap();
+        assert_eq!(result, Err(ChannelError::WriterClosed));
+        let err = tx.write(&[1]).now_or_never().unwrap().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotConnected);
+    }
+
+    #[test]
+    fn read_until_spans_wrap() {
+        let (tx, rx) = RingBuffer::with_capacity(8);
+        let mut line = Vec::new();
+
+        tx.write_all(b"abcdef").now_or_never().unwrap().unwrap();
+        rx.read_until(b'f', &mut line)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        line.clear();
+
+        // Two bytes fit before the end of storage; the delimiter lands after the wrap.
+        tx.write_all(b"ghijk\nl").now_or_never().unwrap().unwrap();
+        let n = rx
+            .read_until(b'\n', &mut line)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert_eq!(n, 6);
+        assert_eq!(line, b"ghijk\n");
+
+        line.clear();
+        drop(tx);
+        assert_eq!(
+            rx.read_until(b'\n', &mut line)
+                .now_or_never()
+                .unwrap()
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            rx.read_until(b'\n', &mut line)
+                .now_or_never()
+                .unwrap()
+                .unwrap(),
+            0
+        );
+        assert_eq!(line, b"l");
+    }
+
+    #[test]
+    fn readable_waits_for_data_or_close() {
+        let (tx, rx) = RingBuffer::with_capacity(4);
+        assert!(rx.readable().now_or_never().is_none());
+
+        tx.write_all(&[1]).now_or_never().unwrap().unwrap();
+        rx.readable().now_or_never().unwrap().unwrap();
+        assert_eq!(rx.len(), 1);
+
+        rx.read_until(1, &mut Vec::new())
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert!(rx.readable().now_or_never().is_none());
+
+        drop(tx);
+        rx.readable().now_or_never().unwrap().unwrap();
+        assert!(rx.is_empty());
+    }
+
+    #[test]
+    fn writable_waits_for_space() {
+        let (tx, mut rx) = RingBuffer::with_capacity(2);
+        tx.writable().now_or_never().unwrap().unwrap();
+
+        tx.write_all(&[1, 2]).now_or_never().unwrap().unwrap();
+        assert!(tx.writable().now_or_never().is_none());
+
+        rx.read(&mut [0]).now_or_never().unwrap().unwrap();
+        tx.writable().now_or_never().unwrap().unwrap();
+
+        drop(rx);
+        let result = tx.writable().now_or_never().unwrap();
+        assert_eq!(result, Err(ChannelError::ReaderGone { written: 0 }));
+    }
+
+    #[test]
+    fn dropping_reader_in_callback_reaches_writer() {
+        let (tx, rx) = RingBuffer::with_capacity(4);
+        let rx = Rc::new(RefCell::new(Some(rx)));
+        tx.on_full({
+            let rx = rx.clone();
+            move || drop(rx.borrow_mut().take())
+        });
+
+        tx.write_all(&[1, 2, 3, 4]).now_or_never().unwrap().unwrap();
+        assert!(rx.borrow().is_none());
+        assert!(tx.wait_closed().now_or_never().is_some());
+        let result = tx.write_all(&[5]).now_or_never().unwrap();
+        assert_eq!(result, Err(ChannelError::ReaderGone { written: 0 }));
+    }
+
+    #[test]
+    fn callbacks_fire_on_transitions() {
+        use core::cell::Cell;
+
+        let (mut tx, mut rx) = RingBuffer::with_capacity(4);
+        let fulls = Rc::new(Cell::new(0));
+        let empties = Rc::new(Cell::new(0));
+        tx.on_full({
+            let fulls = fulls.clone();
+            move || fulls.set(fulls.get() + 1)
+        });
+        rx.on_empty({
+            let empties = empties.clone();
+            move || empties.set(empties.get() + 1)
+        });
+
+        tx.write_all(&[1, 2, 3]).now_or_never().unwrap().unwrap();
+        assert_eq!(fulls.get(), 0);
+        tx.write_all(&[4]).now_or_never().unwrap().unwrap();
+        assert_eq!(fulls.get(), 1);
+        assert!(tx.write(&[5]).now_or_never().is_none());
+        assert_eq!(fulls.get(), 1);
+
+        let mut buf = [0; 2];
+        rx.read(&mut buf).now_or_never().unwrap().unwrap();
+        assert_eq!(empties.get(), 0);
+        rx.read(&mut buf).now_or_never().unwrap().unwrap();
+        assert_eq!(empties.get(), 1);
+        assert!(rx.read(&mut buf).now_or_never().is_none());
+        assert_eq!(empties.get(), 1);
+    }
+
+    #[test]
+    fn snapshot_round_trips() {
+        let (tx, rx) = RingBuffer::with_capacity(4);
+        tx.write_all(&[1, 2, 3]).now_or_never().unwrap().unwrap();
+        rx.read_until(2, &mut Vec::new())
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        tx.write_all(&[4, 5]).now_or_never().unwrap().unwrap();
+
+        let snapshot = rx.snapshot();
+        assert_eq!(rx.debug_indices(), (2, 5, 4));
+
+        let (_tx, rx) = RingBuffer::restore(snapshot.clone()).unwrap();
+        assert_eq!(rx.snapshot(), snapshot);
+        assert_eq!(*rx.cow_snapshot(), [3, 4, 5]);
+        let mut out = Vec::new();
+        rx.read_until(5, &mut out).now_or_never().unwrap().unwrap();
+        assert_eq!(out, [3, 4, 5]);
+
+        let bad = RingSnapshot {
+            data: Vec::new(),
+            read_idx: 1,
+            write_idx: 0,
+            capacity: 0,
+        };
+        assert!(RingBuffer::restore(bad).is_err());
+        let empty = RingBuffer::with_capacity(0).1.snapshot();
+        assert!(RingBuffer::restore(empty).is_ok());
+
+        let bad = RingSnapshot {
+            write_idx: 7,
+            ..snapshot.clone()
+        };
+        assert!(RingBuffer::restore(bad).is_err());
+        let bad = RingSnapshot {
+            capacity: 5,
+            ..snapshot
+        };
+        assert!(RingBuffer::restore(bad).is_err());
+    }
+
+    #[test]
+    fn duplicate_copies_readable_bytes() {
+        let (tx, rx) = RingBuffer::with_capacity(4);
+        tx.write_all(&[1, 2, 3]).now_or_never().unwrap().unwrap();
+        rx.read_until(1, &mut Vec::new())
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        tx.write_all(&[4, 5]).now_or_never().unwrap().unwrap();
+
+        let (copy_tx, copy_rx) = rx.duplicate();
+        assert_eq!(rx.len(), 4);
+        assert_eq!(copy_rx.len(), 4);
+        drop(tx);
+        drop(copy_tx);
+
+        let (mut original, mut copy) = (Vec::new(), Vec::new());
+        rx.read_until(0, &mut original)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        copy_rx
+            .read_until(0, &mut copy)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert_eq!(original, [2, 3, 4, 5]);
+        assert_eq!(copy, original);
+    }
+
+    #[test]
+    fn fill_buf_exposes_one_segment() {
+        let (tx, rx) = RingBuffer::with_capacity(4);
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        assert!(rx.poll_fill_buf(&mut cx).is_pending());
+
+        tx.write_all(&[1, 2, 3]).now_or_never().unwrap().unwrap();
+        rx.consume(2);
+        tx.write_all(&[4, 5]).now_or_never().unwrap().unwrap();
+
+        match rx.poll_fill_buf(&mut cx) {
+            Poll::Ready(Ok(buf)) => assert_eq!(&*buf, [3, 4]),
+            _ => panic!("data should be ready"),
+        }
+        rx.consume(2);
+        match rx.poll_fill_buf(&mut cx) {
+            Poll::Ready(Ok(buf)) => assert_eq!(&*buf, [5]),
+            _ => panic!("data should be ready"),
+        }
+        rx.consume(1);
+
+        drop(tx);
+        match rx.poll_fill_buf(&mut cx) {
+            Poll::Ready(Ok(buf)) => assert!(buf.is_empty()),
+            _ => panic!("EOF should be ready"),
+        };
+    }
+
+    #[test]
+    fn repeated_parks_clone_waker_once() {
+        use core::{
+            cell::Cell,
+            ptr,
+            task::{RawWaker, RawWakerVTable},
+        };
+
+        thread_local!(static CLONES: Cell<usize> = const { Cell::new(0) });
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+        unsafe fn clone(_: *const ()) -> RawWaker {
+            CLONES.with(|c| c.set(c.get() + 1));
+            RawWaker::new(ptr::null(), &VTABLE)
+        }
+        unsafe fn noop(_: *const ()) {}
+
+        let waker = unsafe { Waker::from_raw(RawWaker::new(ptr::null(), &VTABLE)) };
+        let mut cx = Context::from_waker(&waker);
+        let (_tx, mut rx) = RingBuffer::with_capacity(4);
+        for _ in 0..10 {
+            assert!(Pin::new(&mut rx)
+                .poll_read(&mut cx, &mut [0; 4])
+                .is_pending());
+        }
+        assert_eq!(CLONES.with(Cell::get), 1);
+    }
+
+    #[test]
+    fn reset_for_reuse_after_eof() {
+        let (mut tx, rx) = RingBuffer::with_capacity(4);
+        tx.write_all(&[1, 2, 3]).now_or_never().unwrap().unwrap();
+        tx.close().now_or_never().unwrap().unwrap();
+
+        // Not drained yet.
+        assert!(!rx.reset_for_reuse());
+        let mut out = Vec::new();
+        rx.read_until(0, &mut out).now_or_never().unwrap().unwrap();
+        assert!(rx.reset_for_reuse());
+        assert_eq!(rx.debug_indices(), (0, 0, 4));
+
+        tx.write_all(&[4, 5]).now_or_never().unwrap().unwrap();
+        assert!(!rx.reset_for_reuse());
+        drop(tx);
+        out.clear();
+        rx.read_until(0, &mut out).now_or_never().unwrap().unwrap();
+        assert_eq!(out, [4, 5]);
+
+        // The writer is gone, so there's nothing left to reuse the ring with.
+        assert!(!rx.reset_for_reuse());
+    }
+
+    #[cfg(feature = "urgent")]
+    #[test]
+    fn urgent_byte_bypasses_the_ring() {
+        let (tx, rx) = RingBuffer::with_capacity(4);
+        assert_eq!(rx.take_urgent(), None);
+
+        tx.write_all(&[1, 2]).now_or_never().unwrap().unwrap();
+        tx.send_urgent(7);
+        tx.send_urgent(9);
+        assert_eq!(rx.take_urgent(), Some(9));
+        assert_eq!(rx.take_urgent(), None);
+        assert_eq!(rx.len(), 2);
+    }
+
+    #[test]
+    fn transfers_are_capped_per_poll() {
+        let (mut tx, mut rx) = RingBuffer::with_capacity(8);
+        tx.set_max_transfer_per_poll(NonZeroUsize::new(3));
+        rx.set_max_transfer_per_poll(NonZeroUsize::new(2));
+
+        assert_eq!(tx.write(&[1; 8]).now_or_never().unwrap().unwrap(), 3);
+        tx.write_all(&[2; 5]).now_or_never().unwrap().unwrap();
+        assert_eq!(rx.len(), 8);
+
+        let mut buf = [0; 8];
+        assert_eq!(rx.read(&mut buf).now_or_never().unwrap().unwrap(), 2);
+
+        rx.set_max_transfer_per_poll(None);
+        assert_eq!(rx.read(&mut buf).now_or_never().unwrap().unwrap(), 6);
+    }
+
+    #[test]
+    fn fast_copy_drains_to_eof() {
+        let (tx, rx) = RingBuffer::with_capacity(4);
+        tx.write_all(&[0, 0, 1]).now_or_never().unwrap().unwrap();
+        rx.consume(2);
+
+        // The next write wraps, so the copy has to take two segments.
+        tx.write_all(&[2, 3, 4]).now_or_never().unwrap().unwrap();
+        let mut out = futures::io::Cursor::new(Vec::new());
+        assert!(fast_copy(&rx, &mut out).now_or_never().is_none());
+        assert!(rx.is_empty());
+
+        tx.write_all(&[5]).now_or_never().unwrap().unwrap();
+        drop(tx);
+        let copied = fast_copy(&rx, &mut out).now_or_never().unwrap().unwrap();
+        assert_eq!(copied, 1);
+        assert_eq!(out.into_inner(), [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn busy_poll_stores_no_wakers() {
+        let (mut tx, mut rx) = RingBuffer::with_capacity_busy_poll(1);
+        assert!(rx.read(&mut [0]).now_or_never().is_none());
+        tx.write_all(&[1]).now_or_never().unwrap().unwrap();
+        assert!(tx.write(&[2]).now_or_never().is_none());
+        assert!(tx.wait_closed().now_or_never().is_none());
+        assert!(rx.wait_closed().now_or_never().is_none());
+
+        let rb = rx.rb.borrow();
+        assert!(rb.read_waker.is_none());
+        assert!(rb.write_waker.is_none());
+        assert!(rb.writer_closed_waker.is_none());
+        assert!(rb.reader_gone_waker.is_none());
+    }
+
+    #[cfg(feature = "latency")]
+    #[test]
+    fn latency_tracks_oldest_byte_read() {
+        use core::cell::Cell;
+
+        let now = Rc::new(Cell::new(0));
+        let (tx, mut rx) = RingBuffer::with_clock(8, {
+            let now = now.clone();
+            move || now.get()
+        });
+
+        tx.write_all(&[1, 2]).now_or_never().unwrap().unwrap();
+        now.set(10);
+        tx.write_all(&[3, 4]).now_or_never().unwrap().unwrap();
+
+        now.set(30);
+        let mut buf = [0; 3];
+        rx.read(&mut buf).now_or_never().unwrap().unwrap();
+        assert_eq!(rx.stats().max_latency, 30);
+
+        // The remaining byte came from the second write.
+        now.set(58);
+        rx.read(&mut buf).now_or_never().unwrap().unwrap();
+        let stats = rx.stats();
+        assert_eq!(stats.max_latency, 48);
+        assert_eq!(stats.avg_latency, 32);
+    }
+
+    #[cfg(feature = "rate")]
+    #[test]
+    fn rates_cover_the_last_second() {
+        use core::cell::Cell;
+
+        let now = Rc::new(Cell::new(0));
+        let (tx, rx) = RingBuffer::with_clock(1024, {
+            let now = now.clone();
+            move || now.get()
+        });
+        assert_eq!(tx.write_rate(), 0);
+
+        // 100 bytes every 100ms.
+        for _ in 0..10 {
+            now.set(now.get() + 100_000);
+            tx.write_all(&[0; 100]).now_or_never().unwrap().unwrap();
+        }
+        assert_eq!(tx.write_rate(), 1000);
+        assert_eq!(rx.read_rate(), 0);
+
+        rx.consume(500);
+        now.set(now.get() + 550_000);
+        assert_eq!(rx.read_rate(), 500);
+        assert_eq!(tx.write_rate(), 500);
+
+        // Once the window has slid past the last write, nothing new has been written.
+        now.set(now.get() + 2_000_000);
+        assert_eq!(tx.write_rate(), 0);
+    }
+
+    #[test]
+    fn wait_closed_resolves_when_peer_goes_away() {
+        let (tx, rx) = RingBuffer::with_capacity(4);
+        assert!(tx.wait_closed().now_or_never().is_none());
+        assert!(rx.wait_closed().now_or_never().is_none());
+
+        // Data left in the ring doesn't hold the reader's wait open.
+        tx.write_all(&[1]).now_or_never().unwrap().unwrap();
+        drop(tx);
+        assert_eq!(rx.wait_closed().now_or_never(), Some(()));
+        assert_eq!(rx.len(), 1);
+
+        let (tx, rx) = RingBuffer::with_capacity(4);
+        drop(rx);
+        assert_eq!(tx.wait_closed().now_or_never(), Some(()));
+    }
+
+    #[test]
+    fn close_wakers_are_kept_apart_from_read_waker() {
+        let (tx, mut rx) = RingBuffer::with_capacity(4);
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        assert!(rx.poll_closed(&mut cx).is_pending());
+        assert!(Pin::new(&mut rx).poll_read(&mut cx, &mut [0]).is_pending());
+        assert!(rx.poll_closed(&mut cx).is_pending());
+
+        let rb = rx.rb.borrow();
+        assert!(rb.read_waker.is_some());
+        assert!(rb.writer_closed_waker.is_some());
+        drop(rb);
+        drop(tx);
+        assert!(rx.rb.borrow().writer_closed_waker.is_none());
+        assert!(rx.rb.borrow().read_waker.is_none());
+        assert!(rx.poll_closed(&mut cx).is_ready());
+    }
+
+    #[test]
+    fn writer_poll_closed_wakes_on_reader_drop() {
+        use futures::task::{waker, ArcWake};
+        use std::sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        };
+
+        struct Flag(AtomicBool);
+        impl ArcWake for Flag {
+            fn wake_by_ref(flag: &Arc<Self>) {
+                flag.0.store(true, Ordering::SeqCst);
+            }
+        }
+
+        let flag = Arc::new(Flag(AtomicBool::new(false)));
+        let waker = waker(flag.clone());
+        let mut cx = Context::from_waker(&waker);
+        let (tx, rx) = RingBuffer::with_capacity(1);
+        assert!(tx.poll_closed(&mut cx).is_pending());
+
+        drop(rx);
+        assert!(flag.0.load(Ordering::SeqCst));
+        assert!(tx.poll_closed(&mut cx).is_ready());
+    }
+
+    #[test]
+    fn read_into_uninit_buffer() {
+        let (tx, rx) = RingBuffer::with_capacity(4);
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        let mut buf = [MaybeUninit::uninit(); 8];
+        assert!(rx.poll_read_buf(&mut cx, &mut buf).is_pending());
+
+        tx.write_all(&[1, 2, 3]).now_or_never().unwrap().unwrap();
+        match rx.poll_read_buf(&mut cx, &mut buf) {
+            Poll::Ready(Ok(3)) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        let read: Vec<u8> = buf[..3]
+            .iter()
+            .map(|b| unsafe { b.assume_init() })
+            .collect();
+        assert_eq!(read, [1, 2, 3]);
+
+        drop(tx);
+        assert!(matches!(
+            rx.poll_read_buf(&mut cx, &mut buf),
+            Poll::Ready(Ok(0))
+        ));
+    }
+
+    #[test]
+    fn shared_writer_refs_interleave() {
+        let (tx, mut rx) = RingBuffer::with_capacity(8);
+        let (mut a, mut b) = (&tx, &tx);
+        a.write_all(&[1, 2]).now_or_never().unwrap().unwrap();
+        b.write_all(&[3]).now_or_never().unwrap().unwrap();
+        a.close().now_or_never().unwrap().unwrap();
+        assert!(b.write(&[4]).now_or_never().unwrap().is_err());
+
+        let mut out = Vec::new();
+        rx.read_to_end(&mut out).now_or_never().unwrap().unwrap();
+        assert_eq!(out, [1, 2, 3]);
+    }
+
+    #[test]
+    fn shared_reader_ref_reads() {
+        let (tx, rx) = RingBuffer::with_capacity(4);
+        let rx = Rc::new(rx);
+        tx.write_all(&[1, 2, 3]).now_or_never().unwrap().unwrap();
+        drop(tx);
+
+        let mut buf = [0; 2];
+        (&*rx).read_exact(&mut buf).now_or_never().unwrap().unwrap();
+        assert_eq!(buf, [1, 2]);
+        let mut rest = Vec::new();
+        (&*rx)
+            .read_to_end(&mut rest)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert_eq!(rest, [3]);
+    }
+
+    #[test]
+    fn try_with_capacity_rejects_bad_sizes() {
+        assert!(matches!(
+            RingBuffer::try_with_capacity(0),
+            Err(AllocError::ZeroCapacity)
+        ));
+        assert!(matches!(
+            RingBuffer::try_with_capacity(usize::MAX),
+            Err(AllocError::OutOfMemory)
+        ));
+
+        let (tx, rx) = RingBuffer::try_with_capacity(2).unwrap();
+        tx.write_all(&[1, 2]).now_or_never().unwrap().unwrap();
+        assert_eq!(rx.len(), 2);
+    }
+
+    #[test]
+    fn vectored_writes_gather_across_wrap() {
+        let (mut tx, mut rx) = RingBuffer::with_capacity(4);
+        tx.write_all(&[0; 3]).now_or_never().unwrap().unwrap();
+        rx.read_exact(&mut [0; 3]).now_or_never().unwrap().unwrap();
+
+        // One call fills the tail of storage and wraps around to the front.
+        let bufs = [
+            IoSlice::new(&[1, 2]),
+            IoSlice::new(&[]),
+            IoSlice::new(&[3, 4, 5]),
+        ];
+        let n = tx.write_vectored(&bufs).now_or_never().unwrap().unwrap();
+        assert_eq!(n, 4);
+        let mut buf = [0; 4];
+        rx.read_exact(&mut buf).now_or_never().unwrap().unwrap();
+        assert_eq!(buf, [1, 2, 3, 4]);
+
+        let mut bufs = [IoSlice::new(&[6, 7, 8]), IoSlice::new(&[9, 10])];
+        let mut all = Box::pin(tx.write_all_vectored(&mut bufs));
+        assert!((&mut all).now_or_never().is_none());
+        rx.read_exact(&mut buf).now_or_never().unwrap().unwrap();
+        assert_eq!(buf, [6, 7, 8, 9]);
+        all.now_or_never().unwrap().unwrap();
+
+        let mut bufs = [IoSlice::new(&[11; 4]), IoSlice::new(&[12])];
+        let mut all = Box::pin(tx.write_all_vectored(&mut bufs));
+        assert!((&mut all).now_or_never().is_none());
+        drop(rx);
+        assert_eq!(
+            all.now_or_never().unwrap(),
+            Err(ChannelError::ReaderGone { written: 3 })
+        );
+    }
+
+    #[test]
+    fn vectored_reads_scatter_across_wrap() {
+        let (tx, rx) = RingBuffer::with_capacity(4);
+        tx.write_all(&[0; 3]).now_or_never().unwrap().unwrap();
+        rx.consume(3);
+        tx.write_all(&[1, 2, 3]).now_or_never().unwrap().unwrap();
+
+        let (mut a, mut b) = ([0; 2], [0; 3]);
+        let mut bufs = [IoSliceMut::new(&mut a), IoSliceMut::new(&mut b)];
+        let mut read = Box::pin(rx.read_exact_vectored(&mut bufs));
+        assert!((&mut read).now_or_never().is_none());
+        tx.write_all(&[4, 5]).now_or_never().unwrap().unwrap();
+        assert_eq!(read.now_or_never().unwrap(), Ok(5));
+        assert_eq!((a, b), ([1, 2], [3, 4, 5]));
+
+        tx.write_all(&[6]).now_or_never().unwrap().unwrap();
+        drop(tx);
+        let mut bufs = [IoSliceMut::new(&mut a), IoSliceMut::new(&mut b)];
+        let n = rx.read_exact_vectored(&mut bufs).now_or_never().unwrap();
+        assert_eq!(n, Ok(1));
+        assert_eq!(a[0], 6);
+    }
+
+    #[test]
+    fn pooled_storage_is_reused() {
+        #[derive(Default)]
+        struct Pool(RefCell<Vec<Vec<u8>>>);
+        impl BufferPool for Pool {
+            fn acquire(&self, n: usize) -> Vec<u8> {
+                self.0
+                    .borrow_mut()
+                    .pop()
+                    .unwrap_or_else(|| Vec::with_capacity(n))
+            }
+            fn release(&self, buf: Vec<u8>) {
+                self.0.borrow_mut().push(buf);
+            }
+        }
+
+        let pool = Rc::new(Pool::default());
+        let (tx, rx) = RingBuffer::with_capacity_pooled(4, pool.clone());
+        tx.write_all(&[1, 2, 3]).now_or_never().unwrap().unwrap();
+        drop(tx);
+        assert!(pool.0.borrow().is_empty());
+        drop(rx);
+        assert_eq!(pool.0.borrow()[0], [1, 2, 3, 0]);
+
+        // The stale contents don't leak into the next ring.
+        let (tx, rx) = RingBuffer::with_capacity_pooled(2, pool.clone());
+        assert!(pool.0.borrow().is_empty());
+        assert_eq!(rx.debug_indices(), (0, 0, 2));
+        assert_eq!(rx.snapshot().data, [0, 0]);
+        drop((tx, rx));
+        assert_eq!(pool.0.borrow().len(), 1);
+    }
+
+    #[cfg(feature = "bytes")]
+    #[test]
+    fn read_bytes_spans_wrap() {
+        let (tx, rx) = RingBuffer::with_capacity(4);
+        assert!(rx.read_bytes(4).is_empty());
+        tx.write_all(&[0; 3]).now_or_never().unwrap().unwrap();
+        rx.consume(3);
+        tx.write_all(&[1, 2, 3]).now_or_never().unwrap().unwrap();
+
+        assert_eq!(rx.read_bytes(2), [1, 2][..]);
+        assert_eq!(rx.read_bytes(8), [3][..]);
+        assert!(rx.is_empty());
+    }
+
+    #[test]
+    fn peek_waits_for_enough_bytes() {
+        let (tx, rx) = RingBuffer::with_capacity(4);
+        let mut out = [0; 3];
+        tx.write_all(&[1]).now_or_never().unwrap().unwrap();
+        assert!(rx.peek_at_least(2, &mut out).now_or_never().is_none());
+
+        tx.write_all(&[2, 3, 4]).now_or_never().unwrap().unwrap();
+        assert_eq!(rx.peek_at_least(2, &mut out).now_or_never().unwrap(), Ok(3));
+        assert_eq!(out, [1, 2, 3]);
+        assert_eq!(rx.len(), 4);
+
+        // More than the ring can hold settles for a full ring.
+        assert_eq!(rx.peek_at_least(9, &mut out).now_or_never().unwrap(), Ok(3));
+
+        rx.consume(3);
+        drop(tx);
+        assert_eq!(rx.peek_at_least(2, &mut out).now_or_never().unwrap(), Ok(1));
+        assert_eq!(out[0], 4);
+    }
+
+    #[test]
+    fn skip_discards_across_refills() {
+        let (tx, rx) = RingBuffer::with_capacity(4);
+        tx.write_all(&[1, 2, 3]).now_or_never().unwrap().unwrap();
+        assert_eq!(rx.discard(1), 1);
+
+        let mut skip = Box::pin(rx.skip(5));
+        assert!((&mut skip).now_or_never().is_none());
+        tx.write_all(&[4, 5, 6, 7]).now_or_never().unwrap().unwrap();
+        assert_eq!(skip.now_or_never().unwrap(), Ok(5));
+        assert_eq!(rx.len(), 1);
+
+        drop(tx);
+        assert_eq!(rx.skip(3).now_or_never().unwrap(), Ok(1));
+        assert_eq!(rx.discard(1), 0);
+    }
+
+    #[test]
+    fn copy_until_stops_after_delimiter() {
+        let (tx, rx) = RingBuffer::with_capacity(4);
+        let mut out = futures::io::Cursor::new(Vec::new());
+        tx.write_all(&[0; 3]).now_or_never().unwrap().unwrap();
+        rx.consume(3);
+
+        // The delimiter lands after the wrap point.
+        tx.write_all(&[1, 2]).now_or_never().unwrap().unwrap();
+        let mut copy = Box::pin(rx.copy_until(b'\n', &mut out));
+        assert!((&mut copy).now_or_never().is_none());
+        tx.write_all(&[b'\n', 3]).now_or_never().unwrap().unwrap();
+        assert_eq!(copy.now_or_never().unwrap().unwrap(), 3);
+        assert_eq!(out.get_ref(), &[1, 2, b'\n']);
+        assert_eq!(rx.len(), 1);
+
+        drop(tx);
+        let copied = rx.copy_until(b'\n', &mut out).now_or_never().unwrap();
+        assert_eq!(copied.unwrap(), 1);
+        assert_eq!(out.into_inner(), [1, 2, b'\n', 3]);
+    }
+
+    #[test]
+    fn wrap_splits_are_counted() {
+        let (tx, rx) = RingBuffer::with_capacity(4);
+        tx.write_all(&[0; 3]).now_or_never().unwrap().unwrap();
+        rx.consume(3);
+        tx.write_all(&[1, 2]).now_or_never().unwrap().unwrap();
+
+        // Dropping bytes across the wrap copies nothing.
+        rx.consume(2);
+        assert_eq!(rx.wrap_splits(), 0);
+
+        // A vectored write picking its slice up again at the front is one split write.
+        let bufs = [IoSlice::new(&[3, 4, 5, 6])];
+        (&tx).write_vectored(&bufs).now_or_never().unwrap().unwrap();
+        assert_eq!(rx.wrap_splits(), 1);
+
+        // Reading it back out in one go is one split read.
+        rx.consume(2);
+        tx.write_all(&[7, 8]).now_or_never().unwrap().unwrap();
+        let mut out = Vec::new();
+        rx.read_until(8, &mut out).now_or_never().unwrap().unwrap();
+        assert_eq!(out, [5, 6, 7, 8]);
+        assert_eq!(rx.wrap_splits(), 2);
+    }
+
+    #[cfg(feature = "latency")]
+    #[test]
+    fn stats_include_wrap_splits() {
+        let (tx, rx) = RingBuffer::with_capacity(2);
+        tx.write_all(&[1]).now_or_never().unwrap().unwrap();
+        rx.consume(1);
+        tx.write_all(&[2, 3]).now_or_never().unwrap().unwrap();
+        rx.read_until(3, &mut Vec::new())
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert_eq!(rx.stats().wrap_splits, 1);
+        assert_eq!(rx.stats().max_latency, 0);
+    }
+
+    #[test]
+    fn duplex_carries_both_directions() {
+        let (mut a, mut b) = RingBuffer::duplex(4);
+        let mut buf = [0; 4];
+
+        a.write_all(b"ping").now_or_never().unwrap().unwrap();
+        b.read_exact(&mut buf).now_or_never().unwrap().unwrap();
+        assert_eq!(&buf, b"ping");
+
+        b.write_all(b"pong").now_or_never().unwrap().unwrap();
+        assert!(b.read(&mut buf).now_or_never().is_none());
+        a.read_exact(&mut buf).now_or_never().unwrap().unwrap();
+        assert_eq!(&buf, b"pong");
+
+        // Closing one end is a half-close: the other direction keeps working.
+        a.close().now_or_never().unwrap().unwrap();
+        assert_eq!(b.read(&mut buf).now_or_never().unwrap().unwrap(), 0);
+        b.write_all(b"bye").now_or_never().unwrap().unwrap();
+        let (_tx, rx) = a.into_split();
+        assert_eq!(rx.len(), 3);
+    }
+
+    #[test]
+    fn writer_dropped_while_guard_alive() {
+        let (tx, rx) = RingBuffer::with_capacity(4);
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        tx.write_all(&[1, 2]).now_or_never().unwrap().unwrap();
+
+        let guard = match rx.poll_fill_buf(&mut cx) {
+            Poll::Ready(Ok(guard)) => guard,
+            _ => panic!("data should be ready"),
+        };
+        drop(tx);
+        assert_eq!(*guard, [1, 2]);
+        assert!(!rx.rb.try_borrow().unwrap().writer_closed);
+
+        // The close lands as soon as the guard is released.
+        drop(guard);
+        assert!(rx.poll_closed(&mut cx).is_ready());
+        rx.consume(2);
+        match rx.poll_fill_buf(&mut cx) {
+            Poll::Ready(Ok(buf)) => assert!(buf.is_empty()),
+            _ => panic!("EOF should be ready"),
+        };
+    }
+
+    #[test]
+    #[should_panic]
+    fn writing_while_guard_alive_panics() {
+        let (tx, rx) = RingBuffer::with_capacity(4);
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        tx.write_all(&[1]).now_or_never().unwrap().unwrap();
+        let _guard = rx.poll_fill_buf(&mut cx);
+        let _ = tx.write_all(&[2]).now_or_never();
+    }
+
+    proptest! {
+        #[test]
+        fn it_works(capacity in any::<u8>(),
+                    operations in any::<Vec<Operation>>()) {
+            let capacity = capacity as usize;
+            let mut model = Model::new(capacity);
+            let (mut tx, mut rx) = RingBuffer::with_capacity(capacity);
+
+            for op in operations {
+                match op {
+                    Operation::Write(data) => {
+                        let written = tx.write(&data).now_or_never().unwrap_or(Ok(0)).expect("can't fail");
+
+                        // We might have written less than the full buffer due to wrapping, but we
+                        // should make *some* progress as long as there's space.
+                        prop_assert!(model.is_full() || data.is_empty() || written > 0);
+                        prop_assert_eq!(model.write(&data[..written]), written);
+                    }
+
+                    Operation::Read(n) => {
+                        let n = n as usize;
+                        let mut buf = [0; 256];
+                        let nread = rx.read(&mut buf[..n]).now_or_never().unwrap_or(Ok(0)).expect("can't fail");
+
+                        // We might have read less than the full buffer due to wrapping, but we
+                        // should make *some* progress as long as there's some data.
+                        prop_assert!(model.is_empty() || n == 0 || nread > 0);
+                        prop_assert_eq!(model.read(nread), &buf[..nread]);
+                    }
+                }
+
+                prop_assert!(rx == model.to_reader());
+                prop_assert!(rx == model.data[..]);
+            }
         }
     }
 }
@@ -0,0 +1,144 @@
+//! Optional instrumentation of the traffic through a ring.
+
+use alloc::collections::VecDeque;
+
+/// A monotonic time source, in whatever units the caller likes (ticks, microseconds, ...).
+///
+/// Any `Fn() -> u64` closure works, so the ring doesn't need to know about a particular runtime
+/// or hardware timer. Closures are assumed to count microseconds.
+pub trait Clock {
+    fn now(&self) -> u64;
+
+    /// How many units of [`Clock::now`] make up a second, used to turn throughput into
+    /// bytes/second.
+    fn ticks_per_second(&self) -> u64 {
+        1_000_000
+    }
+}
+
+impl<F: Fn() -> u64> Clock for F {
+    fn now(&self) -> u64 {
+        self()
+    }
+}
+
+/// Write-to-read latency measured by a ring built with [`RingBuffer::with_clock`], in the
+/// clock's units, alongside a count of transfers split by the wrap point.
+///
+/// Each read samples the age of the oldest byte it consumed.
+///
+/// [`RingBuffer::with_clock`]: crate::RingBuffer::with_clock
+#[cfg(feature = "latency")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub max_latency: u64,
+    /// Exponentially weighted moving average, weighting each new sample by 1/8.
+    pub avg_latency: u64,
+    /// Same as [`Reader::wrap_splits`], which is counted with or without a clock.
+    ///
+    /// [`Reader::wrap_splits`]: crate::Reader::wrap_splits
+    pub wrap_splits: u64,
+}
+
+/// Tags byte positions with the time they were written so reads can tell how long they waited.
+///
+/// Holds one entry per distinct write timestamp still in the ring, so it's bounded by the ring's
+/// capacity but does allocate as it grows.
+#[cfg(feature = "latency")]
+#[derive(Default)]
+pub(crate) struct LatencyTracker {
+    writes: VecDeque<(u64, u64)>,
+    write_pos: u64,
+    read_pos: u64,
+    samples: u64,
+    stats: Stats,
+}
+
+#[cfg(feature = "latency")]
+impl LatencyTracker {
+    pub(crate) fn wrote(&mut self, now: u64, amount: usize) {
+        if amount == 0 {
+            return;
+        }
+        if self.writes.back().map(|&(_, at)| at) != Some(now) {
+            self.writes.push_back((self.write_pos, now));
+        }
+        self.write_pos += amount as u64;
+    }
+
+    pub(crate) fn read(&mut self, now: u64, amount: usize) {
+        if amount == 0 {
+            return;
+        }
+
+        // The front entry is the write that the oldest unread byte came from.
+        if let Some(&(_, written_at)) = self.writes.front() {
+            self.sample(now.saturating_sub(written_at));
+        }
+
+        self.read_pos += amount as u64;
+        while self.writes.len() > 1 && self.writes[1].0 <= self.read_pos {
+            self.writes.pop_front();
+        }
+        if self.read_pos == self.write_pos {
+            self.writes.clear();
+        }
+    }
+
+    pub(crate) fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    fn sample(&mut self, latency: u64) {
+        let stats = &mut self.stats;
+        stats.max_latency = stats.max_latency.max(latency);
+        stats.avg_latency = if self.samples == 0 {
+            latency
+        } else if latency >= stats.avg_latency {
+            stats.avg_latency + (latency - stats.avg_latency) / 8
+        } else {
+            stats.avg_latency - (stats.avg_latency - latency) / 8
+        };
+        self.samples += 1;
+    }
+}
+
+/// Bytes moved over the last second, kept as per-interval counts so old traffic can age out.
+#[cfg(feature = "rate")]
+#[derive(Default)]
+pub(crate) struct RateSampler {
+    buckets: VecDeque<(u64, u64)>,
+}
+
+#[cfg(feature = "rate")]
+impl RateSampler {
+    /// The window is split into this many buckets, which bounds memory use no matter how often
+    /// bytes move.
+    const BUCKETS: u64 = 16;
+
+    pub(crate) fn record(&mut self, now: u64, ticks_per_second: u64, amount: usize) {
+        if amount == 0 {
+            return;
+        }
+        let step = (ticks_per_second / Self::BUCKETS).max(1);
+        let start = now - now % step;
+        match self.buckets.back_mut() {
+            Some((at, bytes)) if *at == start => *bytes += amount as u64,
+            _ => self.buckets.push_back((start, amount as u64)),
+        }
+        self.expire(now, ticks_per_second);
+    }
+
+    /// Bytes/second over the last second, or 0 if nothing moved in that time.
+    pub(crate) fn rate(&mut self, now: u64, ticks_per_second: u64) -> u64 {
+        self.expire(now, ticks_per_second);
+        self.buckets.iter().map(|&(_, bytes)| bytes).sum()
+    }
+
+    fn expire(&mut self, now: u64, window: u64) {
+        let cutoff = now.saturating_sub(window);
+        while self.buckets.front().is_some_and(|&(at, _)| at < cutoff) {
+            self.buckets.pop_front();
+        }
+    }
+}
@@ -0,0 +1,20 @@
+//! Smoke test for the `no_std` feature's `core` + `alloc` code paths.
+//!
+//! Run with `cargo test --features no_std`; without the feature this file compiles to nothing.
+//! This runs on the host with `std` linked (as `futures::io` requires), so it checks that the
+//! crate builds and behaves with the feature on, not that it works on a target without `std`.
+#![cfg(feature = "no_std")]
+
+use futures::prelude::*;
+use wait_around::RingBuffer;
+
+#[test]
+fn round_trip() {
+    let (mut tx, mut rx) = RingBuffer::with_capacity(8);
+    let written = tx.write(b"hello").now_or_never().unwrap().unwrap();
+    assert_eq!(written, 5);
+
+    let mut buf = [0; 8];
+    let read = rx.read(&mut buf).now_or_never().unwrap().unwrap();
+    assert_eq!(&buf[..read], b"hello");
+}